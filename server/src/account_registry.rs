@@ -0,0 +1,347 @@
+use crate::apple_auth::AccountStore;
+use crate::crypto::EncryptionService;
+use crate::database::Database;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+const DEFAULT_SESSION_TTL_DAYS: i64 = 30;
+const SESSION_EXPIRING_DAYS: i64 = 3;
+// last_used 写回数据库的最小间隔，避免每个请求都写库
+const LAST_USED_PERSIST_MINUTES: i64 = 60;
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionHealth {
+    Active,
+    Expiring,
+    Unauthenticated,
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub account: AccountStore,
+    pub region: String,
+    pub last_used: DateTime<Utc>,
+}
+
+/// 已登录账号的会话注册表：token -> AccountStore
+///
+/// 所有接口通过同一个注册表校验 token；设置了 `EncryptionService` 时会话
+/// 写入 `accounts` 表（认证信息加密保存），超过 TTL 未使用的会话会被移除。
+pub struct AccountRegistry {
+    sessions: RwLock<HashMap<String, Session>>,
+    db: Option<Arc<Database>>,
+    encryption: Option<Arc<EncryptionService>>,
+    ttl: chrono::Duration,
+}
+
+impl AccountRegistry {
+    pub fn new(db: Option<Arc<Database>>) -> Self {
+        Self {
+            sessions: RwLock::new(HashMap::new()),
+            db,
+            encryption: None,
+            ttl: chrono::Duration::days(DEFAULT_SESSION_TTL_DAYS),
+        }
+    }
+
+    pub fn with_encryption(mut self, encryption: Arc<EncryptionService>) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: chrono::Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// 从数据库恢复会话，返回恢复的数量
+    pub async fn restore(&self) -> usize {
+        let (Some(db), Some(encryption)) = (&self.db, &self.encryption) else {
+            return 0;
+        };
+
+        let saved_accounts = match db.get_all_accounts() {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                log::error!("Failed to load saved accounts: {}", e);
+                return 0;
+            }
+        };
+
+        let mut sessions = self.sessions.write().await;
+        for account in saved_accounts {
+            // 旧记录没有 last_used，退回到 updated_at
+            let last_used = account
+                .last_used
+                .as_deref()
+                .or(account.updated_at.as_deref())
+                .and_then(|ts| NaiveDateTime::parse_from_str(ts, TIMESTAMP_FORMAT).ok())
+                .map(|ts| ts.and_utc())
+                .unwrap_or_else(Utc::now);
+            if Utc::now() - last_used > self.ttl {
+                self.delete_persisted(&account.token);
+                continue;
+            }
+
+            match AccountStore::from_account(&account, encryption) {
+                Ok(store) if store.auth_info.is_some() => {
+                    // 旧版本的明文认证信息立即改为加密保存
+                    if account.auth_info.is_none() {
+                        self.persist(&account.token, &store, &account.region, last_used);
+                    }
+                    sessions.insert(
+                        account.token.clone(),
+                        Session {
+                            account: store,
+                            region: account.region.clone(),
+                            last_used,
+                        },
+                    );
+                }
+                Ok(_) => log::warn!("Saved account {} has no session, skipping", account.email),
+                Err(e) => log::warn!("Failed to restore session for {}: {}", account.email, e),
+            }
+        }
+        sessions.len()
+    }
+
+    /// 注册新登录的账号并返回 token
+    pub async fn insert(&self, account: AccountStore, region: &str) -> String {
+        let token = uuid::Uuid::new_v4().to_string();
+        self.put(&token, account, region).await;
+        token
+    }
+
+    /// 更新已有 token 对应的账号（例如重新认证后）
+    pub async fn update(&self, token: &str, account: AccountStore) -> bool {
+        let region = match self.sessions.read().await.get(token) {
+            Some(session) => session.region.clone(),
+            None => return false,
+        };
+        self.put(token, account, &region).await;
+        true
+    }
+
+    async fn put(&self, token: &str, account: AccountStore, region: &str) {
+        self.persist(token, &account, region, Utc::now());
+        self.sessions.write().await.insert(
+            token.to_string(),
+            Session {
+                account,
+                region: region.to_string(),
+                last_used: Utc::now(),
+            },
+        );
+    }
+
+    /// 按 token 查找账号，过期的会话会被移除
+    pub async fn get(&self, token: &str) -> Option<AccountStore> {
+        self.get_session(token).await.map(|session| session.account)
+    }
+
+    pub async fn get_session(&self, token: &str) -> Option<Session> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions.get_mut(token)?;
+
+        if Utc::now() - session.last_used > self.ttl {
+            sessions.remove(token);
+            drop(sessions);
+            self.delete_persisted(token);
+            return None;
+        }
+
+        let now = Utc::now();
+        let persist =
+            now - session.last_used > chrono::Duration::minutes(LAST_USED_PERSIST_MINUTES);
+        session.last_used = now;
+        let session = session.clone();
+        drop(sessions);
+
+        if persist {
+            if let Some(db) = &self.db {
+                let last_used = now.format(TIMESTAMP_FORMAT).to_string();
+                if let Err(e) = db.update_account_last_used(token, &last_used) {
+                    log::warn!("Failed to persist session last_used: {}", e);
+                }
+            }
+        }
+        Some(session)
+    }
+
+    /// 注销会话，返回被移除的账号
+    pub async fn revoke(&self, token: &str) -> Option<AccountStore> {
+        let removed = self.sessions.write().await.remove(token);
+        self.delete_persisted(token);
+        removed.map(|session| session.account)
+    }
+
+    /// 列出未过期的会话，过期会话在此时一并移除
+    pub async fn list(&self) -> Vec<(String, Session)> {
+        self.purge_expired().await;
+        self.sessions
+            .read()
+            .await
+            .iter()
+            .map(|(token, session)| (token.clone(), session.clone()))
+            .collect()
+    }
+
+    /// 移除所有过期会话，返回移除的数量
+    pub async fn purge_expired(&self) -> usize {
+        let now = Utc::now();
+        let expired: Vec<String> = {
+            let mut sessions = self.sessions.write().await;
+            let expired: Vec<String> = sessions
+                .iter()
+                .filter(|(_, session)| now - session.last_used > self.ttl)
+                .map(|(token, _)| token.clone())
+                .collect();
+            for token in &expired {
+                sessions.remove(token);
+            }
+            expired
+        };
+
+        for token in &expired {
+            self.delete_persisted(token);
+        }
+        expired.len()
+    }
+
+    pub fn session_health(&self, session: &Session) -> SessionHealth {
+        let authenticated = session
+            .account
+            .auth_info
+            .as_ref()
+            .is_some_and(|info| info.password_token.is_some());
+        if !authenticated {
+            return SessionHealth::Unauthenticated;
+        }
+
+        let remaining = self.ttl - (Utc::now() - session.last_used);
+        if remaining < chrono::Duration::days(SESSION_EXPIRING_DAYS) {
+            SessionHealth::Expiring
+        } else {
+            SessionHealth::Active
+        }
+    }
+
+    // 持久化会话，服务重启后 token 仍然有效
+    fn persist(&self, token: &str, account: &AccountStore, region: &str, last_used: DateTime<Utc>) {
+        let (Some(db), Some(encryption)) = (&self.db, &self.encryption) else {
+            return;
+        };
+        let result = account
+            .to_account(token, region, encryption)
+            .and_then(|mut record| {
+                record.last_used = Some(last_used.format(TIMESTAMP_FORMAT).to_string());
+                Ok(db.save_account(&record)?)
+            });
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to persist session for {}: {}",
+                account.account_email,
+                e
+            );
+        }
+    }
+
+    fn delete_persisted(&self, token: &str) {
+        if let Some(db) = &self.db {
+            if let Err(e) = db.delete_account(token) {
+                log::warn!("Failed to delete persisted session: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_insert_get_revoke() {
+        let registry = AccountRegistry::new(None);
+        let token = registry
+            .insert(AccountStore::new("user@example.com"), "US")
+            .await;
+
+        let account = registry.get(&token).await.unwrap();
+        assert_eq!(account.account_email, "user@example.com");
+
+        assert!(registry.revoke(&token).await.is_some());
+        assert!(registry.get(&token).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_session_is_rejected() {
+        let registry = AccountRegistry::new(None).with_ttl(chrono::Duration::zero());
+        let token = registry
+            .insert(AccountStore::new("user@example.com"), "US")
+            .await;
+
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        assert!(registry.get(&token).await.is_none());
+        assert_eq!(registry.list().await.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_list_purges_expired_sessions() {
+        let registry = AccountRegistry::new(None).with_ttl(chrono::Duration::zero());
+        registry
+            .insert(AccountStore::new("user@example.com"), "US")
+            .await;
+
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        assert!(registry.list().await.is_empty());
+        assert!(registry.sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_last_used_survives_restart() {
+        let dir = std::env::temp_dir().join(format!("ipa-registry-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Arc::new(Database::new(dir.join("test.db").to_str().unwrap()).unwrap());
+        let key_manager = Arc::new(crate::key_manager::KeyManager::new());
+        key_manager.rotate_key().unwrap();
+        let encryption = Arc::new(EncryptionService::new(key_manager));
+
+        let mut account = AccountStore::new("user@example.com");
+        account.auth_info = Some(crate::apple_auth::AuthInfo {
+            ds_person_id: Some("1".to_string()),
+            password_token: Some("token".to_string()),
+            display_name: None,
+            email: None,
+            store_front: None,
+        });
+        let registry = AccountRegistry::new(Some(db.clone())).with_encryption(encryption.clone());
+        let token = registry.insert(account, "US").await;
+
+        let last_used = (Utc::now() - chrono::Duration::days(2))
+            .format(TIMESTAMP_FORMAT)
+            .to_string();
+        db.update_account_last_used(&token, &last_used).unwrap();
+
+        let restored = AccountRegistry::new(Some(db.clone())).with_encryption(encryption.clone());
+        assert_eq!(restored.restore().await, 1);
+        let session = restored.sessions.read().await.get(&token).cloned().unwrap();
+        assert_eq!(
+            session.last_used.format(TIMESTAMP_FORMAT).to_string(),
+            last_used
+        );
+
+        // 空闲时间超过 TTL 的会话在恢复时被删除
+        let expired = AccountRegistry::new(Some(db.clone()))
+            .with_encryption(encryption)
+            .with_ttl(chrono::Duration::days(1));
+        assert_eq!(expired.restore().await, 0);
+        assert!(db.get_account_by_token(&token).unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
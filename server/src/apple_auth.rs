@@ -1,34 +1,82 @@
-use reqwest::{header, Client};
+use crate::crypto::EncryptionService;
+use crate::database::Account;
+use crate::error::IpaToolError;
+use crate::plist_codec::{decode_response, decode_response_as, encode_request, PLIST_CONTENT_TYPE};
+use crate::store_models::{DownloadProductResponse, LicenseResponse, StoreResponse, StoreSuccess};
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::{header, Client, Url};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 use std::time::Duration;
 
+// 需要持久化 Cookie 的 Apple 域名
+const APPLE_COOKIE_URLS: [&str; 3] = [
+    "https://auth.itunes.apple.com/",
+    "https://buy.itunes.apple.com/",
+    "https://p25-buy.itunes.apple.com/",
+];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthInfo {
     pub ds_person_id: Option<String>,
     pub password_token: Option<String>,
     pub display_name: Option<String>,
     pub email: Option<String>,
+    #[serde(default)]
+    pub store_front: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Store {
     pub client: Client,
     pub guid: String,
+    cookie_jar: Arc<Jar>,
 }
 
 impl Store {
     pub fn new() -> Self {
+        // 生成 GUID（使用 MAC 地址或随机 UUID）
+        Self::with_guid(Self::generate_guid())
+    }
+
+    pub fn with_guid(guid: String) -> Self {
+        let cookie_jar = Arc::new(Jar::default());
         let client = Client::builder()
             .timeout(Duration::from_secs(30))
+            .cookie_provider(cookie_jar.clone())
             .build()
             .unwrap();
 
-        // 生成 GUID（使用 MAC 地址或随机 UUID）
-        let guid = Self::generate_guid();
+        Store {
+            client,
+            guid,
+            cookie_jar,
+        }
+    }
 
-        Store { client, guid }
+    /// 将 Cookie 导出为 JSON（域名 -> Cookie 头）以便写入数据库
+    pub fn export_cookies(&self) -> String {
+        let cookies: BTreeMap<&str, String> = APPLE_COOKIE_URLS
+            .iter()
+            .filter_map(|url| {
+                let header = self.cookie_jar.cookies(&Url::parse(url).ok()?)?;
+                Some((*url, header.to_str().ok()?.to_string()))
+            })
+            .collect();
+        serde_json::to_string(&cookies).unwrap_or_default()
+    }
+
+    pub fn import_cookies(&self, cookies: &str) -> Result<(), IpaToolError> {
+        let cookies: HashMap<String, String> = serde_json::from_str(cookies)?;
+        for (url, header) in cookies {
+            let url = Url::parse(&url).map_err(|e| IpaToolError::InvalidRequest(e.to_string()))?;
+            for cookie in header.split("; ") {
+                self.cookie_jar.add_cookie_str(cookie, &url);
+            }
+        }
+        Ok(())
     }
 
     fn generate_guid() -> String {
@@ -47,9 +95,10 @@ impl Store {
                 .parse()
                 .unwrap(),
         );
+        // 认证、购买和下载接口的请求体都是 `encode_request` 生成的 XML plist
         headers.insert(
-            "Content-Type",
-            "application/x-www-form-urlencoded".parse().unwrap(),
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static(PLIST_CONTENT_TYPE),
         );
         headers
     }
@@ -59,7 +108,7 @@ impl Store {
         email: &str,
         password: &str,
         mfa: Option<&str>,
-    ) -> Result<HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<HashMap<String, Value>, IpaToolError> {
         let url = format!(
             "https://auth.itunes.apple.com/auth/v1/native/fast?guid={}",
             self.guid
@@ -80,18 +129,27 @@ impl Store {
         auth_data.insert("rmp", Value::Number(serde_json::Number::from(0)));
         auth_data.insert("why", Value::String("signIn".to_string()));
 
-        // 将数据转换为 plist 格式（这里简化处理）
         let response = self
             .client
             .post(&url)
             .headers(Self::get_headers())
-            .form(&auth_data)
+            .body(encode_request(&auth_data)?)
             .send()
             .await?;
 
-        let result: HashMap<String, Value> = response.json().await?;
+        // 账号所属商店区域，例如 "143441-1,29"
+        let store_front = response
+            .headers()
+            .get("x-set-apple-store-front")
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+
+        let result = decode_response(&response.bytes().await?)?;
 
         let mut final_result = result.clone();
+        if let Some(store_front) = store_front {
+            final_result.insert("_storeFront".to_string(), Value::String(store_front));
+        }
         if result.contains_key("failureType") {
             final_result.insert("_state".to_string(), Value::String("failure".to_string()));
         } else {
@@ -106,7 +164,7 @@ impl Store {
         app_identifier: &str,
         app_ver_id: Option<&str>,
         auth_info: &AuthInfo,
-    ) -> Result<HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<StoreResponse<LicenseResponse>, IpaToolError> {
         let url = format!(
             "https://p25-buy.itunes.apple.com/WebObjects/MZFinance.woa/wa/buyProduct?guid={}",
             self.guid
@@ -134,13 +192,11 @@ impl Store {
             .client
             .post(&url)
             .headers(headers)
-            .form(&purchase_data)
+            .body(encode_request(&purchase_data)?)
             .send()
             .await?;
 
-        let mut result: HashMap<String, Value> = response.json().await?;
-        result.insert("_state".to_string(), Value::String("success".to_string()));
-        Ok(result)
+        Self::classify_response(response).await
     }
 
     pub async fn download_product(
@@ -148,7 +204,7 @@ impl Store {
         app_identifier: &str,
         app_ver_id: Option<&str>,
         auth_info: &AuthInfo,
-    ) -> Result<HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<StoreResponse<DownloadProductResponse>, IpaToolError> {
         let url = format!(
             "https://p25-buy.itunes.apple.com/WebObjects/MZFinance.woa/wa/volumeStoreDownloadProduct?guid={}",
             self.guid
@@ -175,13 +231,26 @@ impl Store {
             .client
             .post(&url)
             .headers(headers)
-            .form(&download_data)
+            .body(encode_request(&download_data)?)
             .send()
             .await?;
 
-        let mut result: HashMap<String, Value> = response.json().await?;
-        result.insert("_state".to_string(), Value::String("success".to_string()));
-        Ok(result)
+        Self::classify_response(response).await
+    }
+
+    /// 解析购买和下载接口的响应，并按 HTTP 状态码和失败字段分类
+    async fn classify_response<T: serde::de::DeserializeOwned + StoreSuccess>(
+        response: reqwest::Response,
+    ) -> Result<StoreResponse<T>, IpaToolError> {
+        let http_status = response.status();
+        let body = response.bytes().await?;
+        let result: Value = match decode_response_as(&body) {
+            Ok(result) => result,
+            // 错误状态码时响应体可能不是 plist，仅按状态码分类
+            Err(_) if !http_status.is_success() => Value::Object(Default::default()),
+            Err(e) => return Err(e),
+        };
+        Ok(StoreResponse::classify(http_status.as_u16(), result)?)
     }
 }
 
@@ -207,11 +276,78 @@ impl AccountStore {
         }
     }
 
+    /// 从数据库中的账号记录恢复会话（GUID、认证信息和 Cookie）
+    ///
+    /// 认证信息包含 passwordToken，使用 `encryption` 解密；没有加密数据时
+    /// 读取旧版本的明文 `cookie_user`，重新保存后即改为加密存储。
+    pub fn from_account(
+        account: &Account,
+        encryption: &EncryptionService,
+    ) -> Result<Self, IpaToolError> {
+        let store = match &account.guid {
+            Some(guid) => Store::with_guid(guid.clone()),
+            None => Store::new(),
+        };
+        if let Some(cookies) = &account.cookies {
+            store.import_cookies(cookies)?;
+        }
+
+        let auth_info = match (&account.auth_info, &account.cookie_user) {
+            (Some(sealed), _) => Some(serde_json::from_str(
+                &encryption.decrypt_from_string(sealed)?,
+            )?),
+            (None, Some(legacy)) => Some(serde_json::from_str(legacy)?),
+            (None, None) => None,
+        };
+
+        Ok(AccountStore {
+            store,
+            account_email: account.email.clone(),
+            auth_info,
+        })
+    }
+
+    /// 根据商店区域 ID 推断账号所在地区，未知时默认为 US
+    pub fn region(&self) -> String {
+        self.auth_info
+            .as_ref()
+            .and_then(|info| info.store_front.as_deref())
+            .and_then(|sf| sf.split(['-', ',']).next())
+            .and_then(region_for_store_front)
+            .unwrap_or("US")
+            .to_string()
+    }
+
+    pub fn to_account(
+        &self,
+        token: &str,
+        region: &str,
+        encryption: &EncryptionService,
+    ) -> Result<Account, IpaToolError> {
+        let auth_info = match &self.auth_info {
+            Some(info) => Some(encryption.encrypt_to_string(&serde_json::to_string(info)?)?),
+            None => None,
+        };
+        Ok(Account {
+            id: None,
+            token: token.to_string(),
+            email: self.account_email.clone(),
+            region: region.to_string(),
+            guid: Some(self.store.guid.clone()),
+            cookie_user: None,
+            cookies: Some(self.store.export_cookies()),
+            created_at: None,
+            updated_at: None,
+            auth_info,
+            last_used: None,
+        })
+    }
+
     pub async fn authenticate(
         &mut self,
         password: &str,
         mfa: Option<&str>,
-    ) -> Result<HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<HashMap<String, Value>, IpaToolError> {
         let result = self
             .store
             .authenticate(&self.account_email, password, mfa)
@@ -233,6 +369,10 @@ impl AccountStore {
                     .and_then(|v| v.as_str())
                     .map(String::from),
                 email: Some(self.account_email.clone()),
+                store_front: result
+                    .get("_storeFront")
+                    .and_then(|v| v.as_str())
+                    .map(String::from),
             };
             self.auth_info = Some(auth_info);
         }
@@ -244,8 +384,11 @@ impl AccountStore {
         &self,
         app_identifier: &str,
         app_ver_id: Option<&str>,
-    ) -> Result<HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>> {
-        let auth_info = self.auth_info.as_ref().ok_or("Not authenticated")?;
+    ) -> Result<StoreResponse<DownloadProductResponse>, IpaToolError> {
+        let auth_info = self
+            .auth_info
+            .as_ref()
+            .ok_or_else(|| IpaToolError::Session("Not authenticated".to_string()))?;
         self.store
             .download_product(app_identifier, app_ver_id, auth_info)
             .await
@@ -255,10 +398,101 @@ impl AccountStore {
         &self,
         app_identifier: &str,
         app_ver_id: Option<&str>,
-    ) -> Result<HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>> {
-        let auth_info = self.auth_info.as_ref().ok_or("Not authenticated")?;
+    ) -> Result<StoreResponse<LicenseResponse>, IpaToolError> {
+        let auth_info = self
+            .auth_info
+            .as_ref()
+            .ok_or_else(|| IpaToolError::Session("Not authenticated".to_string()))?;
         self.store
             .ensure_license(app_identifier, app_ver_id, auth_info)
             .await
     }
 }
+
+/// 判断认证失败是否因为需要两步验证码
+pub fn is_mfa_required(result: &HashMap<String, Value>) -> bool {
+    let customer_message = result
+        .get("customerMessage")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+    let failure_type = result
+        .get("failureType")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+
+    failure_type.is_empty()
+        && (customer_message == "MZFinance.BadLogin.Configurator_message"
+            || customer_message
+                .to_lowercase()
+                .contains("verification code"))
+}
+
+fn region_for_store_front(store_front_id: &str) -> Option<&'static str> {
+    let region = match store_front_id {
+        "143441" => "US",
+        "143465" => "CN",
+        "143462" => "JP",
+        "143444" => "GB",
+        "143443" => "DE",
+        "143442" => "FR",
+        "143455" => "CA",
+        "143460" => "AU",
+        "143466" => "KR",
+        "143463" => "HK",
+        "143470" => "TW",
+        "143464" => "SG",
+        "143469" => "RU",
+        "143467" => "IN",
+        "143503" => "BR",
+        "143468" => "MX",
+        "143450" => "IT",
+        "143454" => "ES",
+        "143452" => "NL",
+        _ => return None,
+    };
+    Some(region)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers_match_plist_body() {
+        let headers = Store::get_headers();
+        assert_eq!(headers[header::CONTENT_TYPE], PLIST_CONTENT_TYPE);
+    }
+
+    #[test]
+    fn test_session_roundtrip_through_account() {
+        let mut account_store = AccountStore::new("user@example.com");
+        account_store.auth_info = Some(AuthInfo {
+            ds_person_id: Some("12345".to_string()),
+            password_token: Some("token".to_string()),
+            display_name: None,
+            email: Some("user@example.com".to_string()),
+            store_front: Some("143465-19,29".to_string()),
+        });
+        account_store.store.cookie_jar.add_cookie_str(
+            "mz_at0=abc",
+            &Url::parse("https://p25-buy.itunes.apple.com/").unwrap(),
+        );
+
+        let key_manager = std::sync::Arc::new(crate::key_manager::KeyManager::new());
+        key_manager.rotate_key().unwrap();
+        let encryption = EncryptionService::new(key_manager);
+
+        let account = account_store.to_account("t", "US", &encryption).unwrap();
+        let sealed = account.auth_info.clone().unwrap();
+        assert!(!sealed.contains("passwordToken"));
+        assert!(account.cookie_user.is_none());
+        let restored = AccountStore::from_account(&account, &encryption).unwrap();
+
+        assert_eq!(restored.store.guid, account_store.store.guid);
+        let auth_info = restored.auth_info.clone().unwrap();
+        assert_eq!(auth_info.ds_person_id.as_deref(), Some("12345"));
+        assert_eq!(auth_info.password_token.as_deref(), Some("token"));
+        assert_eq!(restored.region(), "CN");
+        assert_eq!(restored.store.export_cookies(), account.cookies.unwrap());
+    }
+}
@@ -0,0 +1,264 @@
+use crate::database::{Credentials, Database};
+use crate::error::IpaToolError;
+use crate::key_manager::KeyManager;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedPayload {
+    pub key_id: String,
+    pub ciphertext: String,
+    pub iv: String,
+    pub auth_tag: String,
+}
+
+/// 使用 `KeyManager` 中的密钥对账号密码进行 AES-256-GCM 加解密
+pub struct EncryptionService {
+    key_manager: Arc<KeyManager>,
+}
+
+impl EncryptionService {
+    pub fn new(key_manager: Arc<KeyManager>) -> Self {
+        Self { key_manager }
+    }
+
+    pub fn key_manager(&self) -> &Arc<KeyManager> {
+        &self.key_manager
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<EncryptedPayload, IpaToolError> {
+        let key_id = self.key_manager.get_current_key_id()?;
+        let key = self.key_manager.get_current_key()?;
+        encrypt_with_key(&key_id, &key, plaintext)
+    }
+
+    pub fn decrypt(&self, payload: &EncryptedPayload) -> Result<String, IpaToolError> {
+        // 按 key_id 查找密钥，轮换后旧密钥加密的数据仍可解密
+        let key = self.key_manager.get_key_by_id(&payload.key_id)?;
+        decrypt_with_key(&key, payload)
+    }
+
+    /// 加密并序列化为 JSON，用于保存在单个文本列中
+    pub fn encrypt_to_string(&self, plaintext: &str) -> Result<String, IpaToolError> {
+        Ok(serde_json::to_string(&self.encrypt(plaintext)?)?)
+    }
+
+    pub fn decrypt_from_string(&self, sealed: &str) -> Result<String, IpaToolError> {
+        self.decrypt(&serde_json::from_str(sealed)?)
+    }
+
+    pub fn encrypt_credentials(
+        &self,
+        email: &str,
+        password: &str,
+    ) -> Result<Credentials, IpaToolError> {
+        let payload = self.encrypt(password)?;
+        Ok(Credentials {
+            id: None,
+            email: email.to_string(),
+            password_encrypted: payload.ciphertext,
+            key_id: payload.key_id,
+            iv: payload.iv,
+            auth_tag: payload.auth_tag,
+            created_at: None,
+            updated_at: None,
+        })
+    }
+
+    pub fn decrypt_credentials(&self, credentials: &Credentials) -> Result<String, IpaToolError> {
+        self.decrypt(&EncryptedPayload {
+            key_id: credentials.key_id.clone(),
+            ciphertext: credentials.password_encrypted.clone(),
+            iv: credentials.iv.clone(),
+            auth_tag: credentials.auth_tag.clone(),
+        })
+    }
+
+    /// 将仍由旧密钥加密的凭证和账号认证信息用当前密钥重新加密，返回迁移的条数
+    pub fn reencrypt_credentials(&self, db: &Database) -> Result<usize, IpaToolError> {
+        let current_key_id = self.key_manager.get_current_key_id()?;
+        let mut count = 0;
+
+        for credentials in db.get_all_credentials()? {
+            if credentials.key_id == current_key_id {
+                continue;
+            }
+
+            let password = self.decrypt_credentials(&credentials)?;
+            db.save_credentials(&self.encrypt_credentials(&credentials.email, &password)?)?;
+            count += 1;
+        }
+
+        for account in db.get_all_accounts()? {
+            let Some(sealed) = &account.auth_info else {
+                continue;
+            };
+            let payload: EncryptedPayload = serde_json::from_str(sealed)?;
+            if payload.key_id == current_key_id {
+                continue;
+            }
+
+            let auth_info = self.decrypt(&payload)?;
+            db.update_account_auth_info(&account.token, &self.encrypt_to_string(&auth_info)?)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+pub fn encrypt_with_key(
+    key_id: &str,
+    key_hex: &str,
+    plaintext: &str,
+) -> Result<EncryptedPayload, IpaToolError> {
+    let key = decode_key(key_hex)?;
+    let iv: [u8; IV_LEN] = rand::thread_rng().gen();
+    let mut tag = [0u8; TAG_LEN];
+
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&iv),
+        &[],
+        plaintext.as_bytes(),
+        &mut tag,
+    )?;
+
+    Ok(EncryptedPayload {
+        key_id: key_id.to_string(),
+        ciphertext: hex::encode(ciphertext),
+        iv: hex::encode(iv),
+        auth_tag: hex::encode(tag),
+    })
+}
+
+pub fn decrypt_with_key(key_hex: &str, payload: &EncryptedPayload) -> Result<String, IpaToolError> {
+    let key = decode_key(key_hex)?;
+    let iv = hex::decode(&payload.iv)?;
+    let tag = hex::decode(&payload.auth_tag)?;
+    let ciphertext = hex::decode(&payload.ciphertext)?;
+
+    if iv.len() != IV_LEN || tag.len() != TAG_LEN {
+        return Err(IpaToolError::Crypto(
+            "Invalid IV or auth tag length".to_string(),
+        ));
+    }
+
+    let plaintext = decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&iv),
+        &[],
+        &ciphertext,
+        &tag,
+    )
+    .map_err(|_| {
+        IpaToolError::Crypto("Decryption failed: data corrupted or wrong key".to_string())
+    })?;
+
+    Ok(String::from_utf8(plaintext)?)
+}
+
+fn decode_key(key_hex: &str) -> Result<Vec<u8>, IpaToolError> {
+    let key = hex::decode(key_hex)?;
+    if key.len() != 32 {
+        return Err(IpaToolError::Crypto(
+            "Invalid encryption key length".to_string(),
+        ));
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let key_manager = Arc::new(KeyManager::new());
+        key_manager.rotate_key().unwrap();
+        let service = EncryptionService::new(key_manager);
+
+        let creds = service
+            .encrypt_credentials("user@example.com", "secret")
+            .unwrap();
+        assert_ne!(creds.password_encrypted, hex::encode("secret"));
+        assert_eq!(service.decrypt_credentials(&creds).unwrap(), "secret");
+    }
+
+    #[test]
+    fn test_decrypt_after_rotation() {
+        let key_manager = Arc::new(KeyManager::new());
+        key_manager.rotate_key().unwrap();
+        let service = EncryptionService::new(key_manager.clone());
+
+        let creds = service
+            .encrypt_credentials("user@example.com", "secret")
+            .unwrap();
+        key_manager.rotate_key().unwrap();
+        assert_eq!(service.decrypt_credentials(&creds).unwrap(), "secret");
+    }
+
+    #[test]
+    fn test_reencrypt_credentials_after_rotation() {
+        let dir = std::env::temp_dir().join(format!("ipa-crypto-{}", uuid::Uuid::new_v4()));
+        let db = Arc::new(Database::new(dir.join("test.db").to_str().unwrap()).unwrap());
+        let key_manager = Arc::new(KeyManager::with_database(db.clone()));
+        key_manager.init().unwrap();
+        let service = EncryptionService::new(key_manager.clone());
+
+        let creds = service
+            .encrypt_credentials("user@example.com", "secret")
+            .unwrap();
+        db.save_credentials(&creds).unwrap();
+        db.save_account(&crate::database::Account {
+            id: None,
+            token: "t".to_string(),
+            email: "user@example.com".to_string(),
+            region: "US".to_string(),
+            guid: None,
+            cookie_user: None,
+            cookies: None,
+            created_at: None,
+            updated_at: None,
+            auth_info: Some(service.encrypt_to_string("{}").unwrap()),
+            last_used: None,
+        })
+        .unwrap();
+
+        let new_key = key_manager.rotate_key().unwrap();
+        assert_eq!(service.reencrypt_credentials(&db).unwrap(), 2);
+
+        let stored = db.get_credentials("user@example.com").unwrap().unwrap();
+        assert_eq!(stored.key_id, new_key.key_id);
+        key_manager.retire_key(&creds.key_id).unwrap();
+        assert_eq!(service.decrypt_credentials(&stored).unwrap(), "secret");
+        let account = db.get_account_by_token("t").unwrap().unwrap();
+        assert_eq!(
+            service
+                .decrypt_from_string(&account.auth_info.unwrap())
+                .unwrap(),
+            "{}"
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_tampered_ciphertext_rejected() {
+        let key_manager = Arc::new(KeyManager::new());
+        key_manager.rotate_key().unwrap();
+        let service = EncryptionService::new(key_manager);
+
+        let mut payload = service.encrypt("secret").unwrap();
+        payload.auth_tag = hex::encode([0u8; TAG_LEN]);
+        assert!(service.decrypt(&payload).is_err());
+    }
+}
@@ -2,6 +2,10 @@ use rusqlite::{params, Connection, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
+// 按列名读取，避免迁移添加的列改变 `SELECT *` 的列顺序
+const ACCOUNT_COLUMNS: &str =
+    "id, token, email, region, guid, cookie_user, cookies, created_at, updated_at, auth_info, last_used";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     pub id: Option<i64>,
@@ -9,10 +13,15 @@ pub struct Account {
     pub email: String,
     pub region: String,
     pub guid: Option<String>,
+    /// 旧版本以明文 JSON 保存的认证信息，只在迁移时读取，保存时清空
     pub cookie_user: Option<String>,
     pub cookies: Option<String>,
     pub created_at: Option<String>,
     pub updated_at: Option<String>,
+    /// 加密后的认证信息（dsPersonId、passwordToken），`EncryptedPayload` 的 JSON
+    pub auth_info: Option<String>,
+    /// 会话最后一次使用的时间（UTC，`%Y-%m-%d %H:%M:%S`），用于重启后计算空闲过期
+    pub last_used: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,6 +65,14 @@ pub struct DownloadRecord {
     pub progress: Option<i64>,
     pub error: Option<String>,
     pub created_at: Option<String>,
+    /// 与 Apple 提供的校验和一致的 IPA MD5（签名前）
+    pub md5: Option<String>,
+    /// 下载完成后 IPA 文件在下载目录中的路径
+    pub file_path: Option<String>,
+    /// 签名后最终文件的 MD5
+    pub signed_md5: Option<String>,
+    /// 是否通过了 Apple 校验和的校验
+    pub verified: bool,
 }
 
 pub struct Database {
@@ -100,7 +117,9 @@ impl Database {
                 cookie_user TEXT,
                 cookies TEXT,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                auth_info TEXT,
+                last_used DATETIME
             )
         ",
             [],
@@ -155,7 +174,11 @@ impl Database {
                 artist_name TEXT,
                 progress INTEGER DEFAULT 0,
                 error TEXT,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                md5 TEXT,
+                file_path TEXT,
+                signed_md5 TEXT,
+                verified INTEGER DEFAULT 0
             )
         ",
             [],
@@ -189,6 +212,18 @@ impl Database {
                 [],
             );
         }
+        let has_auth_info = table_info
+            .iter()
+            .any(|(_, name, _, _, _, _)| name == "auth_info");
+        if !has_auth_info {
+            let _ = conn.execute("ALTER TABLE accounts ADD COLUMN auth_info TEXT", []);
+        }
+        let has_last_used = table_info
+            .iter()
+            .any(|(_, name, _, _, _, _)| name == "last_used");
+        if !has_last_used {
+            let _ = conn.execute("ALTER TABLE accounts ADD COLUMN last_used DATETIME", []);
+        }
 
         let table_info: Vec<(i32, String, String, bool, i32, bool)> = conn
             .prepare("PRAGMA table_info(download_records)")?
@@ -211,6 +246,16 @@ impl Database {
         let has_error = table_info
             .iter()
             .any(|(_, name, _, _, _, _)| name == "error");
+        let has_md5 = table_info.iter().any(|(_, name, _, _, _, _)| name == "md5");
+        let has_file_path = table_info
+            .iter()
+            .any(|(_, name, _, _, _, _)| name == "file_path");
+        let has_signed_md5 = table_info
+            .iter()
+            .any(|(_, name, _, _, _, _)| name == "signed_md5");
+        let has_verified = table_info
+            .iter()
+            .any(|(_, name, _, _, _, _)| name == "verified");
 
         if !has_progress {
             let _ = conn.execute(
@@ -221,6 +266,24 @@ impl Database {
         if !has_error {
             let _ = conn.execute("ALTER TABLE download_records ADD COLUMN error TEXT", []);
         }
+        if !has_md5 {
+            let _ = conn.execute("ALTER TABLE download_records ADD COLUMN md5 TEXT", []);
+        }
+        if !has_file_path {
+            let _ = conn.execute("ALTER TABLE download_records ADD COLUMN file_path TEXT", []);
+        }
+        if !has_signed_md5 {
+            let _ = conn.execute(
+                "ALTER TABLE download_records ADD COLUMN signed_md5 TEXT",
+                [],
+            );
+        }
+        if !has_verified {
+            let _ = conn.execute(
+                "ALTER TABLE download_records ADD COLUMN verified INTEGER DEFAULT 0",
+                [],
+            );
+        }
 
         let _ = conn.execute("DELETE FROM encryption_keys WHERE key_id IS NULL", []);
 
@@ -229,21 +292,9 @@ impl Database {
 
     pub fn get_all_accounts(&self) -> Result<Vec<Account>> {
         let conn = self.connection.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT * FROM accounts")?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM accounts", ACCOUNT_COLUMNS))?;
         let accounts = stmt
-            .query_map([], |row| {
-                Ok(Account {
-                    id: row.get(0)?,
-                    token: row.get(1)?,
-                    email: row.get(2)?,
-                    region: row.get(3)?,
-                    guid: row.get(4)?,
-                    cookie_user: row.get(5)?,
-                    cookies: row.get(6)?,
-                    created_at: row.get(7)?,
-                    updated_at: row.get(8)?,
-                })
-            })?
+            .query_map([], Self::account_from_row)?
             .filter_map(|r| r.ok())
             .collect();
         Ok(accounts)
@@ -251,42 +302,67 @@ impl Database {
 
     pub fn get_account_by_token(&self, token: &str) -> Result<Option<Account>> {
         let conn = self.connection.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT * FROM accounts WHERE token = ?")?;
-        let account = stmt
-            .query_row(params![token], |row| {
-                Ok(Account {
-                    id: row.get(0)?,
-                    token: row.get(1)?,
-                    email: row.get(2)?,
-                    region: row.get(3)?,
-                    guid: row.get(4)?,
-                    cookie_user: row.get(5)?,
-                    cookies: row.get(6)?,
-                    created_at: row.get(7)?,
-                    updated_at: row.get(8)?,
-                })
-            })
-            .optional()?;
-        Ok(account)
+        conn.query_row(
+            &format!("SELECT {} FROM accounts WHERE token = ?", ACCOUNT_COLUMNS),
+            params![token],
+            Self::account_from_row,
+        )
+        .optional()
+    }
+
+    fn account_from_row(row: &rusqlite::Row) -> Result<Account> {
+        Ok(Account {
+            id: row.get(0)?,
+            token: row.get(1)?,
+            email: row.get(2)?,
+            region: row.get(3)?,
+            guid: row.get(4)?,
+            cookie_user: row.get(5)?,
+            cookies: row.get(6)?,
+            created_at: row.get(7)?,
+            updated_at: row.get(8)?,
+            auth_info: row.get(9)?,
+            last_used: row.get(10)?,
+        })
     }
 
     pub fn save_account(&self, account: &Account) -> Result<()> {
         let conn = self.connection.lock().unwrap();
+        // cookie_user 写入 NULL，清除旧版本留下的明文认证信息
         conn.execute(
-            "INSERT OR REPLACE INTO accounts (token, email, region, guid, cookie_user, cookies) 
-             VALUES (?, ?, ?, ?, ?, ?)",
+            "INSERT OR REPLACE INTO accounts (token, email, region, guid, cookie_user, cookies, auth_info, last_used) 
+             VALUES (?, ?, ?, ?, NULL, ?, ?, COALESCE(?, CURRENT_TIMESTAMP))",
             params![
                 account.token,
                 account.email,
                 account.region,
                 account.guid,
-                account.cookie_user,
                 account.cookies,
+                account.auth_info,
+                account.last_used,
             ],
         )?;
         Ok(())
     }
 
+    pub fn update_account_last_used(&self, token: &str, last_used: &str) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET last_used = ? WHERE token = ?",
+            params![last_used, token],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_account_auth_info(&self, token: &str, auth_info: &str) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET auth_info = ? WHERE token = ?",
+            params![auth_info, token],
+        )?;
+        Ok(())
+    }
+
     pub fn delete_account(&self, token: &str) -> Result<()> {
         let conn = self.connection.lock().unwrap();
         conn.execute("DELETE FROM accounts WHERE token = ?", params![token])?;
@@ -416,6 +492,40 @@ impl Database {
         Ok(keys)
     }
 
+    pub fn update_encryption_key_value(&self, key_id: &str, key_value: &str) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "UPDATE encryption_keys SET key_value = ? WHERE key_id = ?",
+            params![key_value, key_id],
+        )?;
+        Ok(())
+    }
+
+    /// 在同一事务中更新多个密钥，任一密钥不存在或写入失败时全部回滚
+    pub fn update_encryption_key_values(&self, values: &[(String, String)]) -> Result<()> {
+        let mut conn = self.connection.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (key_id, key_value) in values {
+            let updated = tx.execute(
+                "UPDATE encryption_keys SET key_value = ? WHERE key_id = ?",
+                params![key_value, key_id],
+            )?;
+            if updated == 0 {
+                return Err(rusqlite::Error::QueryReturnedNoRows);
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn delete_encryption_key(&self, key_id: &str) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "DELETE FROM encryption_keys WHERE key_id = ?",
+            params![key_id],
+        )?;
+        Ok(())
+    }
+
     pub fn reset_encryption_keys(&self) -> Result<()> {
         let conn = self.connection.lock().unwrap();
         conn.execute("DELETE FROM encryption_keys", [])?;
@@ -426,8 +536,8 @@ impl Database {
         let conn = self.connection.lock().unwrap();
         conn.execute(
             "INSERT INTO download_records 
-             (app_name, app_id, bundle_id, version, account_email, account_region, status, file_size, install_url, artwork_url, artist_name, progress, error) 
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+             (app_name, app_id, bundle_id, version, account_email, account_region, status, file_size, install_url, artwork_url, artist_name, progress, error, md5, file_path, signed_md5, verified) 
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             params![
                 record.app_name,
                 record.app_id,
@@ -442,6 +552,10 @@ impl Database {
                 record.artist_name,
                 record.progress,
                 record.error,
+                record.md5,
+                record.file_path,
+                record.signed_md5,
+                record.verified,
             ],
         )?;
         Ok(conn.last_insert_rowid())
@@ -452,31 +566,47 @@ impl Database {
         let mut stmt =
             conn.prepare("SELECT * FROM download_records ORDER BY download_date DESC")?;
         let records = stmt
-            .query_map([], |row| {
-                Ok(DownloadRecord {
-                    id: row.get(0)?,
-                    app_name: row.get(1)?,
-                    app_id: row.get(2)?,
-                    bundle_id: row.get(3)?,
-                    version: row.get(4)?,
-                    account_email: row.get(5)?,
-                    account_region: row.get(6)?,
-                    download_date: row.get(7)?,
-                    status: row.get(8)?,
-                    file_size: row.get(9)?,
-                    install_url: row.get(10)?,
-                    artwork_url: row.get(11)?,
-                    artist_name: row.get(12)?,
-                    progress: row.get(13)?,
-                    error: row.get(14)?,
-                    created_at: row.get(15)?,
-                })
-            })?
+            .query_map([], Self::download_record_from_row)?
             .filter_map(|r| r.ok())
             .collect();
         Ok(records)
     }
 
+    pub fn get_download_record(&self, id: i64) -> Result<Option<DownloadRecord>> {
+        let conn = self.connection.lock().unwrap();
+        conn.query_row(
+            "SELECT * FROM download_records WHERE id = ?",
+            params![id],
+            Self::download_record_from_row,
+        )
+        .optional()
+    }
+
+    fn download_record_from_row(row: &rusqlite::Row) -> Result<DownloadRecord> {
+        Ok(DownloadRecord {
+            id: row.get(0)?,
+            app_name: row.get(1)?,
+            app_id: row.get(2)?,
+            bundle_id: row.get(3)?,
+            version: row.get(4)?,
+            account_email: row.get(5)?,
+            account_region: row.get(6)?,
+            download_date: row.get(7)?,
+            status: row.get(8)?,
+            file_size: row.get(9)?,
+            install_url: row.get(10)?,
+            artwork_url: row.get(11)?,
+            artist_name: row.get(12)?,
+            progress: row.get(13)?,
+            error: row.get(14)?,
+            created_at: row.get(15)?,
+            md5: row.get(16)?,
+            file_path: row.get(17)?,
+            signed_md5: row.get(18)?,
+            verified: row.get::<_, Option<bool>>(19)?.unwrap_or(false),
+        })
+    }
+
     pub fn delete_download_record(&self, id: i64) -> Result<()> {
         let conn = self.connection.lock().unwrap();
         conn.execute("DELETE FROM download_records WHERE id = ?", params![id])?;
@@ -490,7 +620,8 @@ impl Database {
              app_name = ?, app_id = ?, bundle_id = ?, version = ?, 
              account_email = ?, account_region = ?, status = ?, 
              file_size = ?, install_url = ?, artwork_url = ?, 
-             artist_name = ?, progress = ?, error = ?
+             artist_name = ?, progress = ?, error = ?, md5 = ?, file_path = ?,
+             signed_md5 = ?, verified = ?
              WHERE id = ?",
             params![
                 updates.app_name,
@@ -506,12 +637,25 @@ impl Database {
                 updates.artist_name,
                 updates.progress,
                 updates.error,
+                updates.md5,
+                updates.file_path,
+                updates.signed_md5,
+                updates.verified,
                 id,
             ],
         )?;
         Ok(())
     }
 
+    pub fn update_download_progress(&self, id: i64, progress: i64) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "UPDATE download_records SET progress = ? WHERE id = ?",
+            params![progress, id],
+        )?;
+        Ok(())
+    }
+
     pub fn clear_all_download_records(&self) -> Result<()> {
         let conn = self.connection.lock().unwrap();
         conn.execute("DELETE FROM download_records", [])?;
@@ -0,0 +1,167 @@
+use crate::error::IpaToolError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// 断点续传清单，保存在分块缓存目录中
+///
+/// 记录下载地址、文件大小、ETag 和已完成的分块范围。重新开始下载时，
+/// 只有与清单匹配且大小正确的分块会被保留，其余分块重新下载。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadManifest {
+    pub url: String,
+    pub file_size: u64,
+    pub etag: Option<String>,
+    pub chunk_size: u64,
+    /// 已完成的分块，闭区间字节范围
+    pub completed: Vec<(u64, u64)>,
+}
+
+impl DownloadManifest {
+    pub fn new(url: &str, file_size: u64, etag: Option<String>, chunk_size: u64) -> Self {
+        Self {
+            url: url.to_string(),
+            file_size,
+            etag,
+            chunk_size,
+            completed: Vec::new(),
+        }
+    }
+
+    /// 读取缓存目录中的清单，并保留与当前下载匹配、分块文件完整的记录
+    ///
+    /// 清单不存在、损坏或与当前文件不匹配时返回一个空清单。
+    pub async fn restore(
+        cache_dir: &Path,
+        url: &str,
+        file_size: u64,
+        etag: Option<String>,
+        chunk_size: u64,
+    ) -> Self {
+        let mut manifest = Self::new(url, file_size, etag, chunk_size);
+        let Some(saved) = Self::load(cache_dir).await else {
+            return manifest;
+        };
+        if !manifest.same_file(&saved) {
+            log::info!(
+                "Download manifest in {:?} is stale, starting over",
+                cache_dir
+            );
+            return manifest;
+        }
+
+        for (start, end) in saved.completed {
+            let part = manifest.part_path(cache_dir, start);
+            let complete = match fs::metadata(&part).await {
+                Ok(meta) => meta.len() == end - start + 1,
+                Err(_) => false,
+            };
+            if complete {
+                manifest.completed.push((start, end));
+            }
+        }
+        manifest
+    }
+
+    async fn load(cache_dir: &Path) -> Option<Self> {
+        let data = fs::read(cache_dir.join(MANIFEST_FILE)).await.ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    /// 先写临时文件再重命名，避免中断时留下半个清单
+    pub async fn save(&self, cache_dir: &Path) -> Result<(), IpaToolError> {
+        let tmp = cache_dir.join(format!("{}.tmp", MANIFEST_FILE));
+        fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp, cache_dir.join(MANIFEST_FILE)).await?;
+        Ok(())
+    }
+
+    // Apple 的下载地址每次请求都会带上不同的签名参数，
+    // 有 ETag 时按 ETag 判断，否则只比较不含查询参数的路径
+    fn same_file(&self, other: &Self) -> bool {
+        if self.file_size != other.file_size || self.chunk_size != other.chunk_size {
+            return false;
+        }
+        match (&self.etag, &other.etag) {
+            (Some(a), Some(b)) => a == b,
+            _ => strip_query(&self.url) == strip_query(&other.url),
+        }
+    }
+
+    pub fn is_completed(&self, start: u64) -> bool {
+        self.completed.iter().any(|(s, _)| *s == start)
+    }
+
+    pub fn mark_completed(&mut self, start: u64, end: u64) {
+        if !self.is_completed(start) {
+            self.completed.push((start, end));
+        }
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.completed.iter().map(|(s, e)| e - s + 1).sum()
+    }
+
+    /// 分块文件路径，按起始偏移计算序号
+    pub fn part_path(&self, cache_dir: &Path, start: u64) -> PathBuf {
+        cache_dir.join(format!("part{}", start / self.chunk_size))
+    }
+}
+
+fn strip_query(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_restore_keeps_only_complete_parts() {
+        let dir = std::env::temp_dir().join(format!("ipa-manifest-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).await.unwrap();
+
+        let mut manifest = DownloadManifest::new(
+            "https://example.com/a.ipa?sig=1",
+            25,
+            Some("\"abc\"".to_string()),
+            10,
+        );
+        manifest.mark_completed(0, 9);
+        manifest.mark_completed(10, 19);
+        manifest.save(&dir).await.unwrap();
+        fs::write(manifest.part_path(&dir, 0), [0u8; 10])
+            .await
+            .unwrap();
+        // 第二个分块只写了一半
+        fs::write(manifest.part_path(&dir, 10), [0u8; 4])
+            .await
+            .unwrap();
+
+        let restored = DownloadManifest::restore(
+            &dir,
+            "https://example.com/a.ipa?sig=2",
+            25,
+            Some("\"abc\"".to_string()),
+            10,
+        )
+        .await;
+        assert_eq!(restored.completed, vec![(0, 9)]);
+        assert_eq!(restored.downloaded(), 10);
+
+        let changed = DownloadManifest::restore(
+            &dir,
+            "https://example.com/a.ipa",
+            25,
+            Some("\"def\"".to_string()),
+            10,
+        )
+        .await;
+        assert!(changed.completed.is_empty());
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
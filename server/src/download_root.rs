@@ -0,0 +1,192 @@
+use crate::error::IpaToolError;
+use crate::file_naming::sanitize_filename;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+
+/// 所有下载文件的根目录
+///
+/// 调用方传入的子目录和文件名都在根目录下解析，规范化后仍超出根目录
+/// （`..`、绝对路径、指向外部的符号链接）的请求会被拒绝。
+#[derive(Debug, Clone)]
+pub struct DownloadRoot {
+    root: PathBuf,
+}
+
+impl DownloadRoot {
+    /// 创建根目录并保存其规范化路径
+    pub fn new(root: impl AsRef<Path>) -> Result<Self, IpaToolError> {
+        std::fs::create_dir_all(root.as_ref())?;
+        let root = std::fs::canonicalize(root.as_ref())?;
+        Ok(Self { root })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// 解析根目录下的子目录并创建，`None` 或空字符串表示根目录本身
+    pub async fn resolve_dir(&self, sub: Option<&str>) -> Result<PathBuf, IpaToolError> {
+        let sub = sub.map(str::trim).unwrap_or_default();
+        if sub.is_empty() {
+            return Ok(self.root.clone());
+        }
+
+        let relative = Path::new(sub);
+        let safe = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !safe {
+            return Err(escape_error(sub));
+        }
+
+        // 先规范化最深的已存在祖先并检查，防止经由指向根目录之外的符号链接
+        // 创建目录；检查通过后再逐级创建缺少的部分
+        let mut existing = self.root.join(relative);
+        let mut missing = Vec::new();
+        let mut dir = loop {
+            match fs::canonicalize(&existing).await {
+                Ok(dir) => break dir,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    // 悬空的符号链接同样无法确认目标位置
+                    if fs::symlink_metadata(&existing).await.is_ok() {
+                        return Err(escape_error(sub));
+                    }
+                    let (Some(name), Some(parent)) = (existing.file_name(), existing.parent())
+                    else {
+                        return Err(escape_error(sub));
+                    };
+                    missing.push(name.to_owned());
+                    existing = parent.to_path_buf();
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if !dir.starts_with(&self.root) {
+            return Err(escape_error(sub));
+        }
+
+        for name in missing.iter().rev() {
+            dir.push(name);
+            match fs::create_dir(&dir).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        // 创建期间目录可能被替换，规范化后再检查一次
+        let dir = fs::canonicalize(&dir).await?;
+        if !dir.starts_with(&self.root) {
+            return Err(escape_error(sub));
+        }
+        Ok(dir)
+    }
+
+    /// 检查已有文件是否位于根目录内，返回规范化后的路径
+    pub fn contains(&self, path: &str) -> Result<PathBuf, IpaToolError> {
+        let resolved = std::fs::canonicalize(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                IpaToolError::NotFound(format!("IPA 文件不存在: {}", path))
+            }
+            _ => e.into(),
+        })?;
+        if !resolved.starts_with(&self.root) {
+            return Err(escape_error(path));
+        }
+        Ok(resolved)
+    }
+
+    /// 在 `dir` 下为 `filename` 生成安全的文件路径
+    pub fn resolve_file(&self, dir: &Path, filename: &str) -> Result<PathBuf, IpaToolError> {
+        if !dir.starts_with(&self.root) {
+            return Err(escape_error(&dir.to_string_lossy()));
+        }
+        Ok(dir.join(sanitize_filename(filename)))
+    }
+}
+
+/// 从下载地址中取出文件名：只使用 URL 路径的最后一段，忽略查询参数和片段
+pub fn filename_from_url(url: &str) -> String {
+    let segment = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back().map(str::to_string))
+        })
+        .filter(|segment| !segment.is_empty())
+        .unwrap_or_else(|| "app.ipa".to_string());
+
+    let name = sanitize_filename(&segment);
+    if name.to_ascii_lowercase().ends_with(".ipa") {
+        name
+    } else {
+        format!("{}.ipa", name)
+    }
+}
+
+fn escape_error(path: &str) -> IpaToolError {
+    IpaToolError::InvalidRequest(format!("下载路径超出下载目录: {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    fn root() -> DownloadRoot {
+        let dir = std::env::temp_dir().join(format!("ipa-root-{}", uuid::Uuid::new_v4()));
+        DownloadRoot::new(dir).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_resolve_dir_rejects_escapes() {
+        let root = root();
+        for path in ["..", "a/../../etc", "/etc", "/tmp/x", "a/.."] {
+            let err = root.resolve_dir(Some(path)).await.unwrap_err();
+            assert_eq!(err.code(), ErrorCode::InvalidRequest, "{}", path);
+        }
+
+        let dir = root.resolve_dir(Some("games/./2024")).await.unwrap();
+        assert_eq!(dir, root.path().join("games/2024"));
+        assert_eq!(root.resolve_dir(None).await.unwrap(), root.path());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_resolve_dir_rejects_symlink_escape() {
+        let root = root();
+        let outside = std::env::temp_dir().join(format!("ipa-outside-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.path().join("out")).unwrap();
+        assert!(root.resolve_dir(Some("out")).await.is_err());
+
+        // 新目录不能经由符号链接创建在根目录之外
+        assert!(root.resolve_dir(Some("out/new")).await.is_err());
+        assert!(!outside.join("new").exists());
+
+        std::os::unix::fs::symlink(outside.join("missing"), root.path().join("dangling")).unwrap();
+        assert!(root.resolve_dir(Some("dangling/new")).await.is_err());
+        assert!(!outside.join("missing").exists());
+
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn test_filename_from_url() {
+        assert_eq!(
+            filename_from_url("https://example.com/a/App.ipa?token=../../x#frag"),
+            "App.ipa"
+        );
+        assert_eq!(
+            filename_from_url("https://example.com/a/..%2F..%2Fetc%2Fpasswd"),
+            "2F.._2Fetc_2Fpasswd.ipa"
+        );
+        assert_eq!(filename_from_url("https://example.com/"), "app.ipa");
+        assert_eq!(filename_from_url("not a url/../../x.ipa"), "app.ipa");
+
+        let root = root();
+        let file = root
+            .resolve_file(root.path(), &filename_from_url("http://h/../../x.ipa"))
+            .unwrap();
+        assert_eq!(file.parent().unwrap(), root.path());
+    }
+}
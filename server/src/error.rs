@@ -0,0 +1,201 @@
+use serde::Serialize;
+
+/// crate 统一的错误类型
+///
+/// 每个变体对应一个稳定的错误码（见 [`IpaToolError::code`]），前端和脚本按错误码
+/// 判断错误类型，错误信息仅用于展示。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpaToolError {
+    /// 登录失败（密码错误、验证码错误、未保存凭证等）
+    Auth(String),
+    /// 需要提交两步验证码才能完成登录
+    MfaRequired(String),
+    /// token 无效或会话已失效
+    Session(String),
+    /// 未获得应用授权或账号区域不匹配
+    License(String),
+    /// Apple Store 拒绝了请求（其他 failureType）
+    Store(String),
+    /// 与 Apple 或下载服务器通信失败
+    Network(String),
+    /// 文件系统或数据库读写失败
+    Storage(String),
+    /// IPA 压缩包读写失败
+    Archive(String),
+    /// 下载的文件与 Apple 提供的校验和不一致
+    Integrity(String),
+    /// 加解密或密钥管理失败
+    Crypto(String),
+    /// 无法解析远端响应
+    InvalidResponse(String),
+    /// 请求参数错误
+    InvalidRequest(String),
+    /// 请求的资源不存在
+    NotFound(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    AuthFailed,
+    /// 需要提交两步验证码（与认证失败区分，客户端据此弹出验证码输入）
+    MfaRequired,
+    SessionInvalid,
+    LicenseRequired,
+    StoreError,
+    NetworkError,
+    StorageError,
+    ArchiveError,
+    ChecksumMismatch,
+    CryptoError,
+    InvalidResponse,
+    InvalidRequest,
+    NotFound,
+}
+
+impl IpaToolError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            IpaToolError::Auth(_) => ErrorCode::AuthFailed,
+            IpaToolError::MfaRequired(_) => ErrorCode::MfaRequired,
+            IpaToolError::Session(_) => ErrorCode::SessionInvalid,
+            IpaToolError::License(_) => ErrorCode::LicenseRequired,
+            IpaToolError::Store(_) => ErrorCode::StoreError,
+            IpaToolError::Network(_) => ErrorCode::NetworkError,
+            IpaToolError::Storage(_) => ErrorCode::StorageError,
+            IpaToolError::Archive(_) => ErrorCode::ArchiveError,
+            IpaToolError::Integrity(_) => ErrorCode::ChecksumMismatch,
+            IpaToolError::Crypto(_) => ErrorCode::CryptoError,
+            IpaToolError::InvalidResponse(_) => ErrorCode::InvalidResponse,
+            IpaToolError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            IpaToolError::NotFound(_) => ErrorCode::NotFound,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            IpaToolError::Auth(message)
+            | IpaToolError::MfaRequired(message)
+            | IpaToolError::Session(message)
+            | IpaToolError::License(message)
+            | IpaToolError::Store(message)
+            | IpaToolError::Network(message)
+            | IpaToolError::Storage(message)
+            | IpaToolError::Archive(message)
+            | IpaToolError::Integrity(message)
+            | IpaToolError::Crypto(message)
+            | IpaToolError::InvalidResponse(message)
+            | IpaToolError::InvalidRequest(message)
+            | IpaToolError::NotFound(message) => message,
+        }
+    }
+
+    /// 在错误信息前加上说明，错误类型保持不变
+    pub fn context(self, prefix: &str) -> Self {
+        let wrap = |message: String| format!("{}: {}", prefix, message);
+        match self {
+            IpaToolError::Auth(m) => IpaToolError::Auth(wrap(m)),
+            IpaToolError::MfaRequired(m) => IpaToolError::MfaRequired(wrap(m)),
+            IpaToolError::Session(m) => IpaToolError::Session(wrap(m)),
+            IpaToolError::License(m) => IpaToolError::License(wrap(m)),
+            IpaToolError::Store(m) => IpaToolError::Store(wrap(m)),
+            IpaToolError::Network(m) => IpaToolError::Network(wrap(m)),
+            IpaToolError::Storage(m) => IpaToolError::Storage(wrap(m)),
+            IpaToolError::Archive(m) => IpaToolError::Archive(wrap(m)),
+            IpaToolError::Integrity(m) => IpaToolError::Integrity(wrap(m)),
+            IpaToolError::Crypto(m) => IpaToolError::Crypto(wrap(m)),
+            IpaToolError::InvalidResponse(m) => IpaToolError::InvalidResponse(wrap(m)),
+            IpaToolError::InvalidRequest(m) => IpaToolError::InvalidRequest(wrap(m)),
+            IpaToolError::NotFound(m) => IpaToolError::NotFound(wrap(m)),
+        }
+    }
+}
+
+impl std::fmt::Display for IpaToolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for IpaToolError {}
+
+impl From<reqwest::Error> for IpaToolError {
+    fn from(e: reqwest::Error) -> Self {
+        IpaToolError::Network(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for IpaToolError {
+    fn from(e: std::io::Error) -> Self {
+        IpaToolError::Storage(e.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for IpaToolError {
+    fn from(e: rusqlite::Error) -> Self {
+        IpaToolError::Storage(e.to_string())
+    }
+}
+
+impl From<zip::result::ZipError> for IpaToolError {
+    fn from(e: zip::result::ZipError) -> Self {
+        IpaToolError::Archive(e.to_string())
+    }
+}
+
+impl From<plist::Error> for IpaToolError {
+    fn from(e: plist::Error) -> Self {
+        IpaToolError::InvalidResponse(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for IpaToolError {
+    fn from(e: serde_json::Error) -> Self {
+        IpaToolError::InvalidResponse(e.to_string())
+    }
+}
+
+impl From<openssl::error::ErrorStack> for IpaToolError {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        IpaToolError::Crypto(e.to_string())
+    }
+}
+
+impl From<hex::FromHexError> for IpaToolError {
+    fn from(e: hex::FromHexError) -> Self {
+        IpaToolError::Crypto(e.to_string())
+    }
+}
+
+impl From<base64::DecodeError> for IpaToolError {
+    fn from(e: base64::DecodeError) -> Self {
+        IpaToolError::InvalidResponse(e.to_string())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for IpaToolError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        IpaToolError::Crypto(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_is_stable_json() {
+        let err = IpaToolError::Session("无效的 token".to_string()).context("下载失败");
+        assert_eq!(
+            serde_json::to_value(err.code()).unwrap(),
+            serde_json::json!("session_invalid")
+        );
+        assert_eq!(err.to_string(), "下载失败: 无效的 token");
+
+        let err = IpaToolError::MfaRequired("需要两步验证码".to_string());
+        assert_eq!(
+            serde_json::to_value(err.code()).unwrap(),
+            serde_json::json!("mfa_required")
+        );
+    }
+}
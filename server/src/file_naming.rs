@@ -0,0 +1,250 @@
+use crate::error::IpaToolError;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{name}_{version}";
+// 文件名的最大字节数（不含扩展名），留出碰撞后缀的空间
+const MAX_FILENAME_BYTES: usize = 200;
+
+/// 输出文件已存在时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// 覆盖已有文件
+    Overwrite,
+    /// 追加 `_2`、`_3` 等后缀
+    #[default]
+    Suffix,
+    /// 已有文件的校验和与本次下载一致时直接复用，否则按 `Suffix` 处理
+    SkipIfIdentical,
+}
+
+impl CollisionPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "overwrite" => Some(CollisionPolicy::Overwrite),
+            "suffix" => Some(CollisionPolicy::Suffix),
+            "skip" | "skip-if-identical" => Some(CollisionPolicy::SkipIfIdentical),
+            _ => None,
+        }
+    }
+}
+
+/// 文件名模板中可用的字段
+#[derive(Debug, Clone, Default)]
+pub struct FilenameFields<'a> {
+    pub name: &'a str,
+    pub bundle_id: &'a str,
+    pub version: &'a str,
+    pub build: &'a str,
+    pub app_id: &'a str,
+    pub account: &'a str,
+    pub date: &'a str,
+}
+
+/// 输出文件的命名规则
+///
+/// 模板占位符：`{name}`、`{bundleId}`、`{version}`、`{build}`、`{appId}`、
+/// `{account}`、`{date}`，渲染结果会去掉路径中不安全的字符。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileNaming {
+    pub template: String,
+    pub collision: CollisionPolicy,
+}
+
+impl Default for FileNaming {
+    fn default() -> Self {
+        Self {
+            template: DEFAULT_FILENAME_TEMPLATE.to_string(),
+            collision: CollisionPolicy::default(),
+        }
+    }
+}
+
+/// 输出路径的决定结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputTarget {
+    /// 写入新文件
+    Write(PathBuf),
+    /// 已有内容相同的文件，无需重新下载
+    Existing(PathBuf),
+}
+
+impl FileNaming {
+    /// 渲染不含扩展名的文件名
+    pub fn render(&self, fields: &FilenameFields) -> String {
+        let values = [
+            ("name", fields.name),
+            ("bundleId", fields.bundle_id),
+            ("version", fields.version),
+            ("build", fields.build),
+            ("appId", fields.app_id),
+            ("account", fields.account),
+            ("date", fields.date),
+        ];
+        let rendered = values
+            .iter()
+            .fold(self.template.clone(), |text, (key, value)| {
+                text.replace(&format!("{{{}}}", key), &sanitize_filename(value))
+            });
+        sanitize_filename(&rendered)
+    }
+
+    /// 按碰撞策略决定 `dir` 下的输出路径
+    ///
+    /// `expected_md5` 为 Apple 提供的校验和，与已有文件旁的 `.md5` 记录比较。
+    pub async fn resolve(
+        &self,
+        dir: &Path,
+        stem: &str,
+        expected_md5: Option<&str>,
+    ) -> Result<OutputTarget, IpaToolError> {
+        let path = dir.join(format!("{}.ipa", stem));
+        if self.collision == CollisionPolicy::Overwrite || !exists(&path).await {
+            return Ok(OutputTarget::Write(path));
+        }
+
+        if self.collision == CollisionPolicy::SkipIfIdentical {
+            if let (Some(expected), Some(recorded)) = (expected_md5, read_md5(&path).await) {
+                if expected.eq_ignore_ascii_case(&recorded) {
+                    return Ok(OutputTarget::Existing(path));
+                }
+            }
+        }
+
+        for n in 2.. {
+            let candidate = dir.join(format!("{}_{}.ipa", stem, n));
+            if !exists(&candidate).await {
+                return Ok(OutputTarget::Write(candidate));
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// 记录输出文件对应的校验和
+///
+/// 第一行为 Apple 校验通过的 MD5（签名前，供 `SkipIfIdentical` 判断），未校验时为空；
+/// 第二行为签名后最终文件的 MD5。
+pub async fn write_md5(
+    path: &Path,
+    md5: Option<&str>,
+    signed_md5: &str,
+) -> Result<(), IpaToolError> {
+    let content = format!("{}\n{}\n", md5.unwrap_or(""), signed_md5);
+    fs::write(md5_path(path), content).await?;
+    Ok(())
+}
+
+async fn read_md5(path: &Path) -> Option<String> {
+    read_md5_line(path, 0).await
+}
+
+/// 读取记录中签名后文件的 MD5
+pub async fn read_signed_md5(path: &Path) -> Option<String> {
+    read_md5_line(path, 1).await
+}
+
+async fn read_md5_line(path: &Path, index: usize) -> Option<String> {
+    let content = fs::read_to_string(md5_path(path)).await.ok()?;
+    let line = content.lines().nth(index)?.trim();
+    (!line.is_empty()).then(|| line.to_string())
+}
+
+fn md5_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".md5");
+    PathBuf::from(name)
+}
+
+async fn exists(path: &Path) -> bool {
+    fs::try_exists(path).await.unwrap_or(false)
+}
+
+/// 替换文件名中不安全的字符
+///
+/// 保留字母数字（含中文等文字）和 `-_.+()[] `，其余字符（路径分隔符、`:`、
+/// emoji 等）替换为 `_`；去掉首尾的点、空格和 `_`，空结果使用 `app`。
+pub fn sanitize_filename(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        let safe = c.is_alphanumeric() || "-_.+()[] ".contains(c);
+        let c = if safe { c } else { '_' };
+        if c == '_' && result.ends_with('_') {
+            continue;
+        }
+        if result.len() + c.len_utf8() > MAX_FILENAME_BYTES {
+            break;
+        }
+        result.push(c);
+    }
+
+    let trimmed = result.trim_matches(|c| c == '.' || c == ' ' || c == '_');
+    if trimmed.is_empty() {
+        "app".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_sanitizes_fields() {
+        let naming = FileNaming {
+            template: "{name}_{version}_{build}_{account}".to_string(),
+            ..Default::default()
+        };
+        let name = naming.render(&FilenameFields {
+            name: "微信: Chat/🎉",
+            version: "8.0",
+            build: "100",
+            account: "user@example.com",
+            ..Default::default()
+        });
+        assert_eq!(name, "微信_ Chat_8.0_100_user_example.com");
+        assert_eq!(sanitize_filename("../.."), "app");
+    }
+
+    #[tokio::test]
+    async fn test_collision_policies() {
+        let dir = std::env::temp_dir().join(format!("ipa-naming-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let existing = dir.join("App_1.0.ipa");
+        fs::write(&existing, b"ipa").await.unwrap();
+        write_md5(&existing, Some("abc"), "signed").await.unwrap();
+        assert_eq!(read_signed_md5(&existing).await.as_deref(), Some("signed"));
+
+        let mut naming = FileNaming::default();
+        assert_eq!(
+            naming.resolve(&dir, "App_1.0", Some("abc")).await.unwrap(),
+            OutputTarget::Write(dir.join("App_1.0_2.ipa"))
+        );
+
+        naming.collision = CollisionPolicy::SkipIfIdentical;
+        assert_eq!(
+            naming.resolve(&dir, "App_1.0", Some("ABC")).await.unwrap(),
+            OutputTarget::Existing(existing.clone())
+        );
+        assert_eq!(
+            naming.resolve(&dir, "App_1.0", Some("def")).await.unwrap(),
+            OutputTarget::Write(dir.join("App_1.0_2.ipa"))
+        );
+
+        // 未经校验的文件不会被视为相同
+        write_md5(&existing, None, "signed").await.unwrap();
+        assert_eq!(
+            naming.resolve(&dir, "App_1.0", Some("abc")).await.unwrap(),
+            OutputTarget::Write(dir.join("App_1.0_2.ipa"))
+        );
+
+        naming.collision = CollisionPolicy::Overwrite;
+        assert_eq!(
+            naming.resolve(&dir, "App_1.0", None).await.unwrap(),
+            OutputTarget::Write(existing)
+        );
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -1,15 +1,55 @@
 use crate::apple_auth::{AccountStore, AuthInfo, Store};
+use crate::download_manifest::DownloadManifest;
+use crate::error::IpaToolError;
+use crate::file_naming::{
+    read_signed_md5, sanitize_filename, write_md5, FileNaming, FilenameFields, OutputTarget,
+};
+use crate::messages::{Locale, Message};
 use crate::signature::SignatureClient;
-use reqwest;
-use serde_json::Value;
-use std::path::Path;
+use crate::store_models::{
+    DownloadProductResponse, FailureKind, FailureResponse, LicenseResponse, StoreResponse,
+};
+use futures::StreamExt;
+use openssl::hash::{Hasher, MessageDigest};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
 use std::time::Duration;
 use tokio::fs::{self};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::broadcast;
 
 const CHUNK_SIZE: usize = 5 * 1024 * 1024;
 const MAX_RETRIES: usize = 5;
 const RETRY_DELAY: u64 = 3000;
+/// 默认同时下载的分块数
+pub const DEFAULT_DOWNLOAD_WORKERS: usize = 4;
+
+// 正在使用的分块缓存目录，同一目录同时只允许一个任务读写分块和清单
+static CACHE_LOCKS: LazyLock<Mutex<HashMap<PathBuf, Weak<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+/// 获取分块缓存目录的锁，同一应用版本的并发任务依次执行
+async fn lock_cache_dir(cache_dir: &Path) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = {
+        let mut locks = CACHE_LOCKS.lock().unwrap();
+        locks.retain(|_, lock| lock.strong_count() > 0);
+        match locks.get(cache_dir).and_then(Weak::upgrade) {
+            Some(lock) => lock,
+            None => {
+                let lock = Arc::new(tokio::sync::Mutex::new(()));
+                locks.insert(cache_dir.to_path_buf(), Arc::downgrade(&lock));
+                lock
+            }
+        }
+    };
+    if let Ok(guard) = lock.clone().try_lock_owned() {
+        return guard;
+    }
+    log::info!("Waiting for another job using {:?}", cache_dir);
+    lock.lock_owned().await
+}
 
 #[derive(Debug, Clone)]
 pub struct DownloadProgress {
@@ -20,14 +60,80 @@ pub struct DownloadProgress {
     pub downloaded: Option<u64>,
 }
 
+/// 接收下载进度的回调，可以是闭包、broadcast 通道或自定义实现
+pub trait ProgressSink: Send + Sync {
+    fn report(&self, progress: DownloadProgress);
+}
+
+impl<F> ProgressSink for F
+where
+    F: Fn(DownloadProgress) + Send + Sync,
+{
+    fn report(&self, progress: DownloadProgress) {
+        self(progress)
+    }
+}
+
+impl ProgressSink for broadcast::Sender<DownloadProgress> {
+    fn report(&self, progress: DownloadProgress) {
+        // 没有订阅者时忽略
+        let _ = self.send(progress);
+    }
+}
+
+/// 丢弃所有进度
+pub struct NoopProgress;
+
+impl ProgressSink for NoopProgress {
+    fn report(&self, _progress: DownloadProgress) {}
+}
+
+impl std::fmt::Debug for dyn ProgressSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressSink")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DownloadResult {
     pub ok: bool,
     pub file: Option<String>,
     pub metadata: Option<DownloadMetadata>,
-    pub error: Option<String>,
+    pub error: Option<IpaToolError>,
     pub needs_reauth: bool,
     pub needs_purchase: bool,
+    /// 与 Apple 校验和一致的 MD5（签名前的文件），未校验时为 `None`
+    pub md5: Option<String>,
+    /// 签名后最终文件的 MD5
+    pub signed_md5: Option<String>,
+    /// 是否通过了 Apple 校验和的校验
+    pub verified: bool,
+}
+
+impl DownloadResult {
+    fn failed(error: IpaToolError) -> Self {
+        DownloadResult {
+            ok: false,
+            file: None,
+            metadata: None,
+            error: Some(error),
+            needs_reauth: false,
+            needs_purchase: false,
+            md5: None,
+            signed_md5: None,
+            verified: false,
+        }
+    }
+
+    fn with_reauth(mut self) -> Self {
+        self.needs_reauth = true;
+        self
+    }
+
+    fn with_purchase(mut self) -> Self {
+        self.needs_purchase = true;
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -39,16 +145,26 @@ pub struct DownloadParams<'a, S: AppleAuthService> {
     pub download_path: &'a str,
     pub auto_purchase: bool,
     pub token: Option<&'a str>,
+    pub locale: Locale,
+    pub progress: Arc<dyn ProgressSink>,
+    /// 同时下载的分块数，至少为 1
+    pub workers: usize,
+    pub naming: &'a FileNaming,
+    /// 共享的 HTTP 客户端，复用连接池
+    pub client: reqwest::Client,
 }
 
 impl<'a, S: AppleAuthService> DownloadParams<'a, S> {
     pub fn on_progress(&self, progress: DownloadProgress) {
-        // 默认实现，什么都不做
-        let _ = progress;
+        self.progress.report(progress);
+    }
+
+    fn text(&self, message: Message) -> String {
+        message.text(self.locale).to_string()
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DownloadMetadata {
     pub bundle_display_name: String,
     pub bundle_short_version_string: String,
@@ -57,145 +173,128 @@ pub struct DownloadMetadata {
     pub artist_name: String,
 }
 
-fn get_value_from_map<'a>(
-    map: &'a std::collections::HashMap<String, Value>,
-    key: &str,
-) -> Option<&'a Value> {
-    map.get(key)
-}
-
-fn is_session_error(result: &std::collections::HashMap<String, Value>) -> bool {
-    let error_msg = get_value_from_map(result, "failureType")
-        .or(get_value_from_map(result, "customerMessage"))
-        .or(get_value_from_map(result, "message"))
-        .and_then(|v: &Value| v.as_str())
-        .unwrap_or("")
-        .to_lowercase();
-
-    let session_error_patterns = [
-        "session expired",
-        "session invalid",
-        "invalid session",
-        "unauthorized",
-        "authentication failed",
-        "token expired",
-        "invalid token",
-        "not authenticated",
-    ];
-
-    session_error_patterns
-        .iter()
-        .any(|pattern| error_msg.contains(pattern))
-}
-
-pub fn get_license_error_message(result: &std::collections::HashMap<String, Value>) -> String {
-    let customer_message = get_value_from_map(result, "customerMessage")
-        .and_then(|v: &Value| v.as_str())
-        .unwrap_or("");
-
-    let failure_type = get_value_from_map(result, "failureType")
-        .and_then(|v: &Value| v.as_str())
-        .unwrap_or("");
+/// 将 Apple 的授权错误映射为当前语言的提示，未知错误时返回原始信息
+pub fn get_license_error_message(failure: &FailureResponse, locale: Locale) -> String {
+    let customer_message = failure.customer_message.as_deref().unwrap_or("");
+    let failure_type = failure.failure_type.as_deref().unwrap_or("");
 
     let error_msg = format!("{} {}", customer_message, failure_type).to_lowercase();
 
     let license_error_map = [
-        ("license not found", "您尚未购买此应用，正在尝试免费获取..."),
-        ("not found", "未找到此应用，请检查 App ID 是否正确"),
-        ("not purchased", "您尚未购买此应用"),
-        ("未购买", "您尚未购买此应用"),
-        ("未找到", "未找到此应用"),
-        ("unauthorized", "无权下载此应用"),
-        ("invalid request", "无效的请求"),
-        ("item not found", "未找到此应用"),
-        ("store front mismatch", "账号区域与应用不匹配"),
-        ("store front error", "账号区域错误，请切换账号区域"),
+        ("license not found", Message::LicenseNotFound),
+        ("not found", Message::AppNotFound),
+        ("not purchased", Message::NotPurchased),
+        ("未购买", Message::NotPurchased),
+        ("未找到", Message::AppNotFound),
+        ("unauthorized", Message::Unauthorized),
+        ("invalid request", Message::InvalidRequest),
+        ("item not found", Message::AppNotFound),
+        ("store front mismatch", Message::StorefrontMismatch),
+        ("store front error", Message::StorefrontError),
     ];
 
     for (key, message) in &license_error_map {
         if error_msg.contains(key) {
-            return message.to_string();
+            return message.text(locale).to_string();
         }
     }
 
-    if !customer_message.is_ascii() {
-        return customer_message.to_string();
+    if customer_message.is_empty() {
+        return Message::DownloadFailed.text(locale).to_string();
     }
-
     customer_message.to_string()
 }
 
+/// 按 `CHUNK_SIZE` 切分文件，返回每个分块的闭区间字节范围
+fn chunk_ranges(file_size: u64) -> Vec<(u64, u64)> {
+    (0..file_size)
+        .step_by(CHUNK_SIZE)
+        .map(|start| (start, (start + CHUNK_SIZE as u64).min(file_size) - 1))
+        .collect()
+}
+
+/// 下载一个分块并整体写入 `output`，失败时按 `RETRY_DELAY` 递增退避重试
 async fn download_chunk(
+    client: &reqwest::Client,
     url: &str,
     start: u64,
     end: u64,
     output: &Path,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let client = reqwest::Client::new();
-
-    for attempt in 0..MAX_RETRIES {
-        let response = client
-            .get(url)
-            .header("Range", format!("bytes={}-{}", start, end))
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            if attempt < MAX_RETRIES - 1 {
-                tokio::time::sleep(Duration::from_millis(RETRY_DELAY * (attempt as u64 + 1))).await;
-                continue;
+) -> Result<(), IpaToolError> {
+    let mut attempt = 0;
+    loop {
+        match fetch_range(client, url, start, end).await {
+            Ok(bytes) => {
+                // 每次重试都覆盖写入，避免重复追加数据
+                fs::write(output, &bytes).await?;
+                return Ok(());
             }
-            return Err(format!("无法获取区块: {}", response.status()).into());
+            Err(e) if attempt + 1 < MAX_RETRIES => {
+                attempt += 1;
+                log::warn!(
+                    "Chunk {}-{} failed (attempt {}/{}): {}",
+                    start,
+                    end,
+                    attempt,
+                    MAX_RETRIES,
+                    e
+                );
+                tokio::time::sleep(Duration::from_millis(RETRY_DELAY * attempt as u64)).await;
+            }
+            Err(e) => return Err(e.context("下载重试次数耗尽")),
         }
-
-        let mut file = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .append(true)
-            .open(output)
-            .await?;
-
-        let bytes = response.bytes().await?;
-        file.write_all(&bytes).await?;
-
-        return Ok(());
     }
-
-    Err("下载重试次数耗尽".into())
 }
 
-async fn clear_cache(cache_dir: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if let Ok(mut entries) = fs::read_dir(cache_dir).await {
-        while let Some(entry) = entries.next_entry().await? {
-            if entry.file_type().await?.is_file() {
-                fs::remove_file(entry.path()).await?;
-            }
-        }
+async fn fetch_range(
+    client: &reqwest::Client,
+    url: &str,
+    start: u64,
+    end: u64,
+) -> Result<bytes::Bytes, IpaToolError> {
+    let response = client
+        .get(url)
+        .header("Range", format!("bytes={}-{}", start, end))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(IpaToolError::Network(format!(
+            "无法获取区块: {}",
+            response.status()
+        )));
     }
-    Ok(())
-}
 
-fn get_artwork_from_map(metadata: &serde_json::Map<String, Value>) -> String {
-    let url_60 = metadata.get("artworkUrl60").and_then(|v| v.as_str());
-    let url_512 = metadata.get("artworkUrl512").and_then(|v| v.as_str());
-    let url_100 = metadata.get("artworkUrl100").and_then(|v| v.as_str());
-    url_60.or(url_512).or(url_100).unwrap_or("").to_string()
+    let bytes = response.bytes().await?;
+    if bytes.len() as u64 != end - start + 1 {
+        return Err(IpaToolError::Network(format!(
+            "区块大小不符: 期望 {} 字节，实际 {} 字节",
+            end - start + 1,
+            bytes.len()
+        )));
+    }
+    Ok(bytes)
 }
 
-fn get_state(app: &std::collections::HashMap<String, Value>) -> Option<&Value> {
-    app.get("_state")
+fn percent_of(downloaded: u64, file_size: u64) -> f64 {
+    if file_size == 0 {
+        return 0.0;
+    }
+    ((downloaded as f64 / file_size as f64) * 100.0)
+        .min(100.0)
+        .floor()
 }
 
-fn get_song_list(app: &std::collections::HashMap<String, Value>) -> Option<&Value> {
-    app.get("songList")
+fn format_mb(bytes: u64) -> String {
+    format!("{:.2}", bytes as f64 / 1024.0 / 1024.0)
 }
 
 pub async fn download_ipa_with_account<S: AppleAuthService>(
     params: DownloadParams<'_, S>,
-) -> Result<DownloadResult, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<DownloadResult, IpaToolError> {
     params.on_progress(DownloadProgress {
         phase: "auth".to_string(),
-        message: "[auth] 查询下载信息".to_string(),
+        message: params.text(Message::QueryDownloadInfo),
         progress: None,
         file_size: None,
         downloaded: None,
@@ -206,211 +305,246 @@ pub async fn download_ipa_with_account<S: AppleAuthService>(
         password_token: None,
         display_name: None,
         email: Some(params.email.to_string()),
+        store_front: None,
     };
 
-    let mut app = params
+    let app = params
         .store
         .download_product(params.appid, params.app_ver_id, &auth_info)
         .await?;
 
-    let state = get_state(&app);
-    if state != Some(&Value::String("success".to_string())) && is_session_error(&app) {
-        params.on_progress(DownloadProgress {
-            phase: "session".to_string(),
-            message: "[session] 检测到会话失效，尝试刷新...".to_string(),
-            progress: None,
-            file_size: None,
-            downloaded: None,
-        });
+    let product = match app {
+        StoreResponse::Success(product) => product,
+        StoreResponse::Failure(failure) => match failure.kind() {
+            FailureKind::SessionExpired | FailureKind::PasswordTokenExpired => {
+                params.on_progress(DownloadProgress {
+                    phase: "session".to_string(),
+                    message: params.text(Message::SessionRefreshing),
+                    progress: None,
+                    file_size: None,
+                    downloaded: None,
+                });
 
-        return Ok(DownloadResult {
-            ok: false,
-            file: None,
-            metadata: None,
-            error: Some("会话已失效，请重新登录".to_string()),
-            needs_reauth: true,
-            needs_purchase: false,
-        });
-    }
+                return Ok(DownloadResult::failed(IpaToolError::Session(
+                    params.text(Message::SessionExpired),
+                ))
+                .with_reauth());
+            }
+            FailureKind::LicenseRequired if params.auto_purchase => {
+                params.on_progress(DownloadProgress {
+                    phase: "auth".to_string(),
+                    message: params.text(Message::Purchasing),
+                    progress: None,
+                    file_size: None,
+                    downloaded: None,
+                });
+
+                let license_result = params
+                    .store
+                    .ensure_license(params.appid, params.app_ver_id, &auth_info)
+                    .await?;
+
+                if let StoreResponse::Failure(failure) = &license_result {
+                    return Ok(DownloadResult::failed(IpaToolError::License(
+                        get_license_error_message(failure, params.locale),
+                    ))
+                    .with_purchase());
+                }
+
+                params.on_progress(DownloadProgress {
+                    phase: "auth".to_string(),
+                    message: params.text(Message::PurchaseSucceeded),
+                    progress: None,
+                    file_size: None,
+                    downloaded: None,
+                });
+
+                match params
+                    .store
+                    .download_product(params.appid, params.app_ver_id, &auth_info)
+                    .await?
+                {
+                    StoreResponse::Success(product) => product,
+                    StoreResponse::Failure(failure) => {
+                        return Ok(DownloadResult::failed(IpaToolError::License(
+                            get_license_error_message(&failure, params.locale),
+                        ))
+                        .with_purchase());
+                    }
+                }
+            }
+            FailureKind::LicenseRequired => {
+                return Ok(DownloadResult::failed(IpaToolError::License(
+                    get_license_error_message(&failure, params.locale),
+                ))
+                .with_purchase());
+            }
+            FailureKind::StorefrontMismatch => {
+                return Ok(DownloadResult::failed(IpaToolError::License(
+                    params.text(Message::StorefrontMismatch),
+                )));
+            }
+            FailureKind::Other => {
+                return Ok(DownloadResult::failed(IpaToolError::from(&failure)));
+            }
+        },
+    };
 
-    let error_msg = get_value_from_map(&app, "failureType")
-        .or(get_value_from_map(&app, "customerMessage"))
-        .or(get_value_from_map(&app, "message"))
-        .and_then(|v: &Value| v.as_str())
-        .unwrap_or("")
-        .to_lowercase();
+    let song = product
+        .song_list
+        .first()
+        .ok_or_else(|| IpaToolError::InvalidResponse("Invalid song list".to_string()))?;
+    let file_url = song.url.as_str();
+    let metadata = &song.metadata;
 
-    let is_license_error = error_msg.contains("license")
-        || error_msg.contains("not found")
-        || error_msg.contains("未购买")
-        || error_msg.contains("未授权");
+    let download_dir = Path::new(params.download_path);
+    fs::create_dir_all(download_dir).await?;
 
-    let current_state = get_state(&app);
-    if current_state != Some(&Value::String("success".to_string())) && is_license_error {
-        if params.auto_purchase {
-            params.on_progress(DownloadProgress {
-                phase: "auth".to_string(),
-                message: "[purchase] 正在购买应用...".to_string(),
-                progress: None,
-                file_size: None,
-                downloaded: None,
-            });
+    let bundle_display_name = metadata.bundle_display_name.as_deref().unwrap_or("Unknown");
+    let bundle_short_version = metadata
+        .bundle_short_version_string
+        .as_deref()
+        .unwrap_or("1.0");
 
-            let license_result = params
-                .store
-                .ensure_license(params.appid, params.app_ver_id, &auth_info)
-                .await?;
-
-            if get_state(&license_result) != Some(&Value::String("success".to_string())) {
-                let error_msg = get_license_error_message(&license_result);
-                return Ok(DownloadResult {
-                    ok: false,
-                    file: None,
-                    metadata: None,
-                    error: Some(error_msg),
-                    needs_reauth: false,
-                    needs_purchase: true,
-                });
-            }
+    let metadata_info = DownloadMetadata {
+        bundle_display_name: bundle_display_name.to_string(),
+        bundle_short_version_string: bundle_short_version.to_string(),
+        bundle_id: metadata.bundle_id.clone().unwrap_or_default(),
+        artwork_url: metadata.artwork().unwrap_or("").to_string(),
+        artist_name: metadata.artist_name.clone().unwrap_or_default(),
+    };
 
+    let date = chrono::Local::now().format("%Y%m%d").to_string();
+    let stem = params.naming.render(&FilenameFields {
+        name: bundle_display_name,
+        bundle_id: metadata.bundle_id.as_deref().unwrap_or_default(),
+        version: bundle_short_version,
+        build: metadata.bundle_version.as_deref().unwrap_or_default(),
+        app_id: params.appid,
+        account: params.email,
+        date: &date,
+    });
+    // 每个应用版本使用独立的分块缓存，保留上次未完成的分块用于续传；
+    // 同一版本的任务先等待前一个任务结束，再决定输出路径
+    let cache_dir = download_dir.join("cache").join(sanitize_filename(&format!(
+        "{}_{}",
+        params.appid, bundle_short_version
+    )));
+    let _cache_guard = lock_cache_dir(&cache_dir).await;
+
+    let output_file_path = match params
+        .naming
+        .resolve(download_dir, &stem, song.md5.as_deref())
+        .await?
+    {
+        OutputTarget::Write(path) => path,
+        OutputTarget::Existing(path) => {
             params.on_progress(DownloadProgress {
-                phase: "auth".to_string(),
-                message: "[purchase] 购买成功，重新查询下载信息".to_string(),
-                progress: None,
+                phase: "done".to_string(),
+                message: Message::AlreadyDownloaded
+                    .render(params.locale, &[("file", &path.to_string_lossy())]),
+                progress: Some(100.0),
                 file_size: None,
                 downloaded: None,
             });
-
-            app = params
-                .store
-                .download_product(params.appid, params.app_ver_id, &auth_info)
-                .await?;
-
-            if get_state(&app) != Some(&Value::String("success".to_string())) {
-                let error_msg = get_license_error_message(&app);
-                return Ok(DownloadResult {
-                    ok: false,
-                    file: None,
-                    metadata: None,
-                    error: Some(error_msg),
-                    needs_reauth: false,
-                    needs_purchase: true,
-                });
-            }
-        } else {
-            let error_msg = get_license_error_message(&app);
             return Ok(DownloadResult {
-                ok: false,
-                file: None,
-                metadata: None,
-                error: Some(error_msg),
+                ok: true,
+                file: Some(path.to_string_lossy().into_owned()),
+                metadata: Some(metadata_info),
+                error: None,
                 needs_reauth: false,
-                needs_purchase: true,
+                needs_purchase: false,
+                md5: song.md5.clone(),
+                signed_md5: read_signed_md5(&path).await,
+                verified: true,
             });
         }
-    }
-
-    if get_state(&app) != Some(&Value::String("success".to_string())) {
-        let error_msg = get_value_from_map(&app, "customerMessage")
-            .and_then(|v: &Value| v.as_str())
-            .unwrap_or("下载失败")
-            .to_string();
-
-        return Ok(DownloadResult {
-            ok: false,
-            file: None,
-            metadata: None,
-            error: Some(error_msg),
-            needs_reauth: false,
-            needs_purchase: false,
-        });
-    }
-
-    let song_list_value = get_song_list(&app)
-        .and_then(|v| v.as_array())
-        .and_then(|v| v.first())
-        .ok_or("Invalid song list")?;
-
-    let song_list = song_list_value
-        .as_object()
-        .ok_or("Invalid song list format")?;
-
-    let file_url = song_list
-        .get("URL")
-        .and_then(|v| v.as_str())
-        .ok_or("Invalid file URL")?;
-
-    let metadata = song_list
-        .get("metadata")
-        .and_then(|v| v.as_object())
-        .ok_or("Invalid metadata")?;
-
-    let download_dir = Path::new(params.download_path);
-    fs::create_dir_all(download_dir).await?;
-
-    let bundle_display_name = metadata
-        .get("bundleDisplayName")
-        .and_then(|v| v.as_str())
-        .unwrap_or("Unknown");
-
-    let bundle_short_version = metadata
-        .get("bundleShortVersionString")
-        .and_then(|v| v.as_str())
-        .unwrap_or("1.0");
+    };
 
-    let output_file_path = download_dir.join(format!(
-        "{}_{}.ipa",
-        bundle_display_name, bundle_short_version
-    ));
-    let cache_dir = download_dir.join("cache");
     fs::create_dir_all(&cache_dir).await?;
-    clear_cache(&cache_dir).await?;
 
-    let response = reqwest::Client::new().get(file_url).send().await?;
+    let client = &params.client;
+    let response = client.head(file_url).send().await?;
 
     if !response.status().is_success() {
-        return Err(format!("无法获取文件: {}", response.status()).into());
+        return Err(IpaToolError::Network(format!(
+            "无法获取文件: {}",
+            response.status()
+        )));
     }
 
-    let file_size = response.content_length().unwrap_or(0);
-    let num_chunks = (file_size as f64 / CHUNK_SIZE as f64).ceil() as usize;
-
+    // HEAD 响应没有响应体，直接读取 Content-Length 头
+    let file_size = response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|&size| size > 0)
+        .ok_or_else(|| IpaToolError::Network("无法获取文件大小".to_string()))?;
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    drop(response);
+
+    let ranges = chunk_ranges(file_size);
+    let num_chunks = ranges.len();
+    let mut manifest =
+        DownloadManifest::restore(&cache_dir, file_url, file_size, etag, CHUNK_SIZE as u64).await;
+    manifest.save(&cache_dir).await?;
+    let mut downloaded = manifest.downloaded();
+
+    let started = if downloaded > 0 {
+        Message::DownloadResumed.render(
+            params.locale,
+            &[
+                ("downloaded", &format_mb(downloaded)),
+                ("size", &format_mb(file_size)),
+            ],
+        )
+    } else {
+        Message::DownloadStarted.render(
+            params.locale,
+            &[("size", &format_mb(file_size)), ("chunks", &num_chunks)],
+        )
+    };
     params.on_progress(DownloadProgress {
         phase: "download-start".to_string(),
-        message: format!(
-            "[download] 开始：{:.2}MB，分块={}",
-            file_size as f64 / 1024.0 / 1024.0,
-            num_chunks
-        ),
-        progress: Some(0.0),
+        message: started,
+        progress: Some(percent_of(downloaded, file_size)),
         file_size: Some(file_size),
-        downloaded: Some(0),
+        downloaded: Some(downloaded),
     });
 
-    let mut downloaded: u64 = 0;
-    let mut progress = vec![0u64; num_chunks];
-
-    for i in 0..num_chunks {
-        let start = (i * CHUNK_SIZE) as u64;
-        let end = std::cmp::min(start + CHUNK_SIZE as u64 - 1, file_size - 1);
-        let temp_output = cache_dir.join(format!("part{}", i));
-        let url = file_url.to_string();
-
-        download_chunk(&url, start, end, &temp_output).await?;
-
-        progress[i] = std::cmp::min(CHUNK_SIZE as u64, file_size - (i * CHUNK_SIZE) as u64);
-        downloaded = progress.iter().sum();
-
-        let percent = ((downloaded as f64 / file_size as f64) * 100.0).min(100.0) as u32;
+    let missing: Vec<_> = ranges
+        .iter()
+        .filter(|(start, _)| !manifest.is_completed(*start))
+        .map(|&(start, end)| (start, end, manifest.part_path(&cache_dir, start)))
+        .collect();
+    let mut chunks = futures::stream::iter(missing)
+        .map(|(start, end, temp_output)| async move {
+            download_chunk(client, file_url, start, end, &temp_output).await?;
+            Ok::<_, IpaToolError>((start, end))
+        })
+        .buffer_unordered(params.workers.max(1));
+
+    while let Some(chunk) = chunks.next().await {
+        let (start, end) = chunk?;
+        manifest.mark_completed(start, end);
+        manifest.save(&cache_dir).await?;
+        downloaded += end - start + 1;
 
         params.on_progress(DownloadProgress {
             phase: "download-progress".to_string(),
-            message: format!(
-                "[download] 进度 {:.2}MB / {:.2}MB",
-                downloaded as f64 / 1024.0 / 1024.0,
-                file_size as f64 / 1024.0 / 1024.0
+            message: Message::DownloadProgress.render(
+                params.locale,
+                &[
+                    ("downloaded", &format_mb(downloaded)),
+                    ("size", &format_mb(file_size)),
+                ],
             ),
-            progress: Some(percent as f64),
+            progress: Some(percent_of(downloaded, file_size)),
             file_size: Some(file_size),
             downloaded: Some(downloaded),
         });
@@ -418,63 +552,97 @@ pub async fn download_ipa_with_account<S: AppleAuthService>(
 
     params.on_progress(DownloadProgress {
         phase: "merge".to_string(),
-        message: "[merge] 合并分块...".to_string(),
+        message: params.text(Message::Merging),
         progress: None,
         file_size: None,
         downloaded: None,
     });
 
-    let mut final_file = fs::OpenOptions::new()
+    let final_file = fs::OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
         .open(&output_file_path)
         .await?;
+    let mut final_file = tokio::io::BufWriter::new(final_file);
 
-    for i in 0..num_chunks {
-        let temp_output = cache_dir.join(format!("part{}", i));
+    // 逐块流式复制，同时计算 MD5，不把分块读入内存
+    let mut hasher = Hasher::new(MessageDigest::md5())?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    for (start, _) in &ranges {
+        let temp_output = manifest.part_path(&cache_dir, *start);
         let mut temp_file = fs::File::open(&temp_output).await?;
-        let mut buffer = Vec::new();
-        temp_file.read_to_end(&mut buffer).await?;
-        final_file.write_all(&buffer).await?;
-        fs::remove_file(&temp_output).await?;
+        loop {
+            let n = temp_file.read(&mut buffer).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n])?;
+            final_file.write_all(&buffer[..n]).await?;
+        }
     }
+    final_file.flush().await?;
+    drop(final_file);
+
+    let actual_md5 = hex::encode(hasher.finish()?);
+    let md5 = match song.md5.as_deref() {
+        Some(expected) if expected.eq_ignore_ascii_case(&actual_md5) => {
+            params.on_progress(DownloadProgress {
+                phase: "verify".to_string(),
+                message: params.text(Message::Verified),
+                progress: None,
+                file_size: None,
+                downloaded: None,
+            });
+            Some(actual_md5)
+        }
+        Some(expected) => {
+            // 无法判断是哪个分块损坏，丢弃全部分块，下次重新下载
+            fs::remove_file(&output_file_path).await?;
+            fs::remove_dir_all(&cache_dir).await?;
+            return Err(IpaToolError::Integrity(Message::ChecksumMismatch.render(
+                params.locale,
+                &[("expected", &expected), ("actual", &actual_md5)],
+            )));
+        }
+        None => {
+            log::warn!("No md5 in song list, skipping integrity check");
+            None
+        }
+    };
 
     params.on_progress(DownloadProgress {
         phase: "sign".to_string(),
-        message: "[sign] 写入签名...".to_string(),
+        message: params.text(Message::Signing),
         progress: None,
         file_size: None,
         downloaded: None,
     });
 
-    let mut sig_client = SignatureClient::new(song_list_value, params.email)?;
-    sig_client.load_file(&output_file_path.to_string_lossy())?;
-    sig_client.append_metadata();
-    sig_client.append_signature()?;
-    sig_client.write()?;
+    // 重写压缩包是阻塞的文件操作，放到阻塞线程池中执行；
+    // 签名会改变文件内容，因此重新计算最终文件的 MD5
+    let mut sig_client = SignatureClient::new(song, params.email)?;
+    let output_path = output_file_path.to_string_lossy().into_owned();
+    let signed_md5 = tokio::task::spawn_blocking(move || {
+        sig_client.load_file(&output_path)?;
+        sig_client.append_metadata()?;
+        sig_client.append_signature()?;
+        sig_client.write()?;
+        file_md5(&output_path)
+    })
+    .await
+    .map_err(|e| IpaToolError::Archive(e.to_string()))??;
 
     fs::remove_dir_all(&cache_dir).await?;
 
-    let metadata_info = DownloadMetadata {
-        bundle_display_name: bundle_display_name.to_string(),
-        bundle_short_version_string: bundle_short_version.to_string(),
-        bundle_id: metadata
-            .get("bundleId")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        artwork_url: get_artwork_from_map(metadata),
-        artist_name: metadata
-            .get("artistName")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-    };
+    write_md5(&output_file_path, md5.as_deref(), &signed_md5).await?;
 
     params.on_progress(DownloadProgress {
         phase: "done".to_string(),
-        message: format!("[done] 产物：{}", output_file_path.to_string_lossy()),
+        message: Message::Done.render(
+            params.locale,
+            &[("file", &output_file_path.to_string_lossy())],
+        ),
         progress: Some(100.0),
         file_size: Some(file_size),
         downloaded: Some(downloaded),
@@ -487,9 +655,20 @@ pub async fn download_ipa_with_account<S: AppleAuthService>(
         error: None,
         needs_reauth: false,
         needs_purchase: false,
+        verified: md5.is_some(),
+        md5,
+        signed_md5: Some(signed_md5),
     })
 }
 
+/// 流式计算文件的 MD5
+fn file_md5(path: &str) -> Result<String, IpaToolError> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Hasher::new(MessageDigest::md5())?;
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finish()?))
+}
+
 #[async_trait::async_trait]
 pub trait AppleAuthService {
     async fn download_product(
@@ -497,14 +676,14 @@ pub trait AppleAuthService {
         app_identifier: &str,
         app_ver_id: Option<&str>,
         auth_info: &AuthInfo,
-    ) -> Result<std::collections::HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>>;
+    ) -> Result<StoreResponse<DownloadProductResponse>, IpaToolError>;
 
     async fn ensure_license(
         &self,
         app_identifier: &str,
         app_ver_id: Option<&str>,
         auth_info: &AuthInfo,
-    ) -> Result<std::collections::HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>>;
+    ) -> Result<StoreResponse<LicenseResponse>, IpaToolError>;
 }
 
 #[async_trait::async_trait]
@@ -514,8 +693,7 @@ impl AppleAuthService for Store {
         app_identifier: &str,
         app_ver_id: Option<&str>,
         auth_info: &AuthInfo,
-    ) -> Result<std::collections::HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>>
-    {
+    ) -> Result<StoreResponse<DownloadProductResponse>, IpaToolError> {
         Store::download_product(self, app_identifier, app_ver_id, auth_info).await
     }
 
@@ -524,8 +702,7 @@ impl AppleAuthService for Store {
         app_identifier: &str,
         app_ver_id: Option<&str>,
         auth_info: &AuthInfo,
-    ) -> Result<std::collections::HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>>
-    {
+    ) -> Result<StoreResponse<LicenseResponse>, IpaToolError> {
         Store::ensure_license(self, app_identifier, app_ver_id, auth_info).await
     }
 }
@@ -537,8 +714,7 @@ impl AppleAuthService for AccountStore {
         app_identifier: &str,
         app_ver_id: Option<&str>,
         _auth_info: &AuthInfo,
-    ) -> Result<std::collections::HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>>
-    {
+    ) -> Result<StoreResponse<DownloadProductResponse>, IpaToolError> {
         AccountStore::download_product(self, app_identifier, app_ver_id).await
     }
 
@@ -547,8 +723,374 @@ impl AppleAuthService for AccountStore {
         app_identifier: &str,
         app_ver_id: Option<&str>,
         _auth_info: &AuthInfo,
-    ) -> Result<std::collections::HashMap<String, Value>, Box<dyn std::error::Error + Send + Sync>>
-    {
+    ) -> Result<StoreResponse<LicenseResponse>, IpaToolError> {
         AccountStore::ensure_license(self, app_identifier, app_ver_id).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_models::{AppMetadata, SinfEntry, SongListItem};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_cache_dir_lock_is_exclusive() {
+        let dir = std::env::temp_dir().join(format!("ipa-cache-{}", uuid::Uuid::new_v4()));
+        let guard = lock_cache_dir(&dir).await;
+
+        let waiting = tokio::spawn({
+            let dir = dir.clone();
+            async move { lock_cache_dir(&dir).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        // 其他目录不受影响
+        drop(lock_cache_dir(&dir.join("other")).await);
+
+        drop(guard);
+        drop(waiting.await.unwrap());
+    }
+
+    #[test]
+    fn test_chunk_ranges_cover_file() {
+        let size = 2 * CHUNK_SIZE as u64 + 10;
+        let ranges = chunk_ranges(size);
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[0], (0, CHUNK_SIZE as u64 - 1));
+        assert_eq!(ranges[2], (2 * CHUNK_SIZE as u64, size - 1));
+        assert!(chunk_ranges(0).is_empty());
+    }
+
+    // 始终返回指定失败信息的 Store，记录购买请求的次数
+    struct FailingStore(FailureResponse, AtomicUsize);
+
+    impl FailingStore {
+        fn new(failure: FailureResponse) -> Self {
+            Self(failure, AtomicUsize::new(0))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AppleAuthService for FailingStore {
+        async fn download_product(
+            &self,
+            _app_identifier: &str,
+            _app_ver_id: Option<&str>,
+            _auth_info: &AuthInfo,
+        ) -> Result<StoreResponse<DownloadProductResponse>, IpaToolError> {
+            Ok(StoreResponse::Failure(self.0.clone()))
+        }
+
+        async fn ensure_license(
+            &self,
+            _app_identifier: &str,
+            _app_ver_id: Option<&str>,
+            _auth_info: &AuthInfo,
+        ) -> Result<StoreResponse<LicenseResponse>, IpaToolError> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            Ok(StoreResponse::Failure(self.0.clone()))
+        }
+    }
+
+    static NAMING: std::sync::LazyLock<FileNaming> = std::sync::LazyLock::new(FileNaming::default);
+
+    fn params<'a>(
+        store: &'a FailingStore,
+        auto_purchase: bool,
+        progress: Arc<dyn ProgressSink>,
+    ) -> DownloadParams<'a, FailingStore> {
+        DownloadParams {
+            store,
+            email: "user@example.com",
+            appid: "1",
+            app_ver_id: None,
+            download_path: "unused",
+            auto_purchase,
+            token: None,
+            locale: Locale::EnUs,
+            progress,
+            workers: 1,
+            naming: &NAMING,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress_closure_sees_purchase_phases() {
+        let store = FailingStore::new(FailureResponse {
+            failure_type: Some("9610".to_string()),
+            customer_message: Some("License not found".to_string()),
+            ..Default::default()
+        });
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let sink = phases.clone();
+
+        let result = download_ipa_with_account(params(
+            &store,
+            true,
+            Arc::new(move |p: DownloadProgress| sink.lock().unwrap().push(p.phase)),
+        ))
+        .await
+        .unwrap();
+
+        assert!(result.needs_purchase);
+        assert_eq!(*phases.lock().unwrap(), vec!["auth", "auth"]);
+        assert_eq!(store.1.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_message_does_not_purchase() {
+        let store = FailingStore::new(FailureResponse {
+            failure_type: Some("5002".to_string()),
+            customer_message: Some("The item you requested could not be found.".to_string()),
+            ..Default::default()
+        });
+
+        let result = download_ipa_with_account(params(&store, true, Arc::new(NoopProgress)))
+            .await
+            .unwrap();
+
+        assert!(!result.ok);
+        assert!(!result.needs_purchase);
+        assert_eq!(store.1.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_progress_broadcast() {
+        let store = FailingStore::new(FailureResponse {
+            failure_type: Some("2034".to_string()),
+            ..Default::default()
+        });
+        let (tx, mut rx) = broadcast::channel(16);
+
+        let result = download_ipa_with_account(params(&store, false, Arc::new(tx)))
+            .await
+            .unwrap();
+
+        assert!(result.needs_reauth);
+        assert_eq!(
+            rx.recv().await.unwrap().message,
+            "[auth] Querying download info"
+        );
+        assert_eq!(rx.recv().await.unwrap().phase, "session");
+    }
+
+    // 按 Range 返回固定内容的本地 HTTP 服务，每个连接处理一个请求
+    async fn serve_bytes(body: Arc<Vec<u8>>) -> String {
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                let body = body.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        let n = socket.read(&mut buf).await.unwrap_or(0);
+                        if n == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    let request = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                    let range = request
+                        .lines()
+                        .find_map(|line| line.strip_prefix("range: bytes="))
+                        .and_then(|range| range.trim().split_once('-'))
+                        .map(|(start, end)| {
+                            let start: usize = start.parse().unwrap();
+                            let end: usize = end.parse().unwrap_or(body.len() - 1);
+                            (start, end.min(body.len() - 1))
+                        });
+                    let (status, content) = match range {
+                        Some((start, end)) => ("206 Partial Content", &body[start..=end]),
+                        None => ("200 OK", &body[..]),
+                    };
+                    let head = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        content.len()
+                    );
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    if !request.starts_with("head ") {
+                        let _ = socket.write_all(content).await;
+                    }
+                });
+            }
+        });
+        format!("http://{}/Demo.ipa", addr)
+    }
+
+    // 包含 SC_Info 清单的最小 IPA，主程序足够大以拆成两个分块
+    fn demo_ipa() -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let stored: zip::write::FileOptions<'_, ()> =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("Payload/Demo.app/SC_Info/Manifest.plist", stored)
+            .unwrap();
+        let mut manifest = plist::Dictionary::new();
+        manifest.insert(
+            "SinfPaths".to_string(),
+            plist::Value::Array(vec![plist::Value::String("SC_Info/Demo.sinf".to_string())]),
+        );
+        plist::to_writer_xml(&mut zip, &plist::Value::Dictionary(manifest)).unwrap();
+        zip.start_file("Payload/Demo.app/Demo", stored).unwrap();
+        let binary: Vec<u8> = (0..CHUNK_SIZE + 1024).map(|i| (i % 251) as u8).collect();
+        std::io::Write::write_all(&mut zip, &binary).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    // 返回指向本地文件的下载信息的 Store
+    struct FixtureStore(SongListItem);
+
+    #[async_trait::async_trait]
+    impl AppleAuthService for FixtureStore {
+        async fn download_product(
+            &self,
+            _app_identifier: &str,
+            _app_ver_id: Option<&str>,
+            _auth_info: &AuthInfo,
+        ) -> Result<StoreResponse<DownloadProductResponse>, IpaToolError> {
+            Ok(StoreResponse::Success(DownloadProductResponse {
+                song_list: vec![self.0.clone()],
+                ..Default::default()
+            }))
+        }
+
+        async fn ensure_license(
+            &self,
+            _app_identifier: &str,
+            _app_ver_id: Option<&str>,
+            _auth_info: &AuthInfo,
+        ) -> Result<StoreResponse<LicenseResponse>, IpaToolError> {
+            panic!("no purchase expected");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_reports_every_phase() {
+        use base64::Engine;
+
+        let ipa = Arc::new(demo_ipa());
+        let size = ipa.len() as u64;
+        let md5 = hex::encode(openssl::hash::hash(MessageDigest::md5(), &ipa).unwrap());
+        let store = FixtureStore(SongListItem {
+            url: serve_bytes(ipa).await,
+            md5: Some(md5.clone()),
+            sinfs: vec![SinfEntry {
+                id: Some(0),
+                sinf: base64::engine::general_purpose::STANDARD.encode(b"sinf"),
+            }],
+            metadata: AppMetadata {
+                bundle_display_name: Some("Demo".to_string()),
+                bundle_short_version_string: Some("1.0".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let dir = std::env::temp_dir().join(format!("ipa-download-{}", uuid::Uuid::new_v4()));
+        let download_path = dir.to_string_lossy().into_owned();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let params = DownloadParams {
+            store: &store,
+            email: "user@example.com",
+            appid: "1",
+            app_ver_id: None,
+            download_path: &download_path,
+            auto_purchase: false,
+            token: None,
+            locale: Locale::EnUs,
+            progress: Arc::new(move |p: DownloadProgress| {
+                sink.lock()
+                    .unwrap()
+                    .push((p.phase, p.file_size, p.downloaded))
+            }),
+            workers: 1,
+            naming: &NAMING,
+            client: reqwest::Client::new(),
+        };
+
+        let result = download_ipa_with_account(params).await.unwrap();
+        assert!(result.ok);
+        assert!(result.verified);
+        assert_eq!(result.md5.as_deref(), Some(md5.as_str()));
+        let signed_md5 = file_md5(result.file.as_deref().unwrap()).unwrap();
+        assert_ne!(signed_md5, md5);
+        assert_eq!(result.signed_md5, Some(signed_md5));
+
+        let events = events.lock().unwrap().clone();
+        let phases: Vec<&str> = events.iter().map(|(phase, _, _)| phase.as_str()).collect();
+        assert_eq!(
+            phases,
+            vec![
+                "auth",
+                "download-start",
+                "download-progress",
+                "download-progress",
+                "merge",
+                "verify",
+                "sign",
+                "done"
+            ]
+        );
+        let chunk = CHUNK_SIZE as u64;
+        assert_eq!(
+            events[1],
+            ("download-start".to_string(), Some(size), Some(0))
+        );
+        assert_eq!(events[2].1, Some(size));
+        assert!(matches!(events[2].2, Some(n) if n == chunk || n == size - chunk));
+        assert_eq!(
+            events[3],
+            ("download-progress".to_string(), Some(size), Some(size))
+        );
+        assert_eq!(events[7].1, Some(size));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_rejects_empty_file() {
+        let store = FixtureStore(SongListItem {
+            url: serve_bytes(Arc::new(Vec::new())).await,
+            metadata: AppMetadata {
+                bundle_display_name: Some("Empty".to_string()),
+                bundle_short_version_string: Some("1.0".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let dir = std::env::temp_dir().join(format!("ipa-download-{}", uuid::Uuid::new_v4()));
+        let download_path = dir.to_string_lossy().into_owned();
+        let params = DownloadParams {
+            store: &store,
+            email: "user@example.com",
+            appid: "1",
+            app_ver_id: None,
+            download_path: &download_path,
+            auto_purchase: false,
+            token: None,
+            locale: Locale::EnUs,
+            progress: Arc::new(|_: DownloadProgress| {}),
+            workers: 1,
+            naming: &NAMING,
+            client: reqwest::Client::new(),
+        };
+
+        let err = download_ipa_with_account(params).await.unwrap_err();
+        assert!(matches!(err, IpaToolError::Network(_)));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
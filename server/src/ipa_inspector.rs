@@ -0,0 +1,608 @@
+use crate::error::IpaToolError;
+use crate::signature::{find_app_bundle, plist_string, read_plist_entry, read_zip};
+use plist::Value;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{Read, Seek};
+use zip::ZipArchive;
+
+// Mach-O 常量
+const FAT_MAGIC: u32 = 0xcafe_babe;
+const FAT_MAGIC_64: u32 = 0xcafe_babf;
+const MH_MAGIC: u32 = 0xfeed_face;
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+const LC_ENCRYPTION_INFO: u32 = 0x21;
+const LC_ENCRYPTION_INFO_64: u32 = 0x2c;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+// 代码签名（大端）
+const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade_0cc0;
+const CSMAGIC_EMBEDDED_ENTITLEMENTS: u32 = 0xfade_7171;
+const CSSLOT_ENTITLEMENTS: u32 = 5;
+// 加载命令和权限声明的读取上限，防止损坏的文件导致大量分配
+const MAX_LOAD_COMMANDS_BYTES: u32 = 4 * 1024 * 1024;
+const MAX_ENTITLEMENTS_BYTES: u32 = 1024 * 1024;
+
+/// IPA 检查结果
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpaInfo {
+    /// `Payload/` 下的应用包名，例如 `Demo.app`
+    pub bundle_name: String,
+    pub bundle_id: Option<String>,
+    pub display_name: Option<String>,
+    pub short_version: Option<String>,
+    pub build_version: Option<String>,
+    pub minimum_os_version: Option<String>,
+    pub supported_devices: Vec<String>,
+    pub required_capabilities: Vec<String>,
+    pub architectures: Vec<String>,
+    pub url_schemes: Vec<String>,
+    /// 主程序是否经过 FairPlay 加密（`LC_ENCRYPTION_INFO` 的 cryptid），无法解析时为空
+    pub encrypted: Option<bool>,
+    /// 代码签名中的权限声明
+    pub entitlements: Option<serde_json::Value>,
+    pub extensions: Vec<BundleComponent>,
+    pub frameworks: Vec<BundleComponent>,
+    pub sizes: ComponentSizes,
+}
+
+/// 应用扩展（`PlugIns/*.appex`）或内嵌框架
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleComponent {
+    pub name: String,
+    pub bundle_id: Option<String>,
+    pub version: Option<String>,
+    /// 扩展类型，例如 `com.apple.widgetkit-extension`
+    pub extension_point: Option<String>,
+    pub size: u64,
+    pub compressed_size: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentSizes {
+    pub total: u64,
+    pub compressed_total: u64,
+    pub main_binary: u64,
+    pub frameworks: u64,
+    pub extensions: u64,
+    /// 资源和其他文件（不含主程序、框架和扩展）
+    pub resources: u64,
+}
+
+/// 检查 IPA：读取 Info.plist、主程序 Mach-O 头、权限声明、扩展和框架
+///
+/// 主程序按流读取，只解析头部、加载命令和代码签名，内存占用与 IPA 大小无关。
+pub fn inspect_ipa(path: &str) -> Result<IpaInfo, IpaToolError> {
+    let mut zip = read_zip(path)?;
+    let bundle = find_app_bundle(&zip)?;
+    let prefix = format!("Payload/{}/", bundle);
+
+    let info = read_plist_entry(&mut zip, &format!("{}Info.plist", prefix))?;
+    let info = info
+        .as_dictionary()
+        .ok_or_else(|| IpaToolError::Archive("Invalid Info.plist".to_string()))?;
+
+    let mut result = IpaInfo {
+        bundle_name: bundle.clone(),
+        bundle_id: plist_string(info, "CFBundleIdentifier"),
+        display_name: plist_string(info, "CFBundleDisplayName")
+            .or_else(|| plist_string(info, "CFBundleName")),
+        short_version: plist_string(info, "CFBundleShortVersionString"),
+        build_version: plist_string(info, "CFBundleVersion"),
+        minimum_os_version: plist_string(info, "MinimumOSVersion"),
+        supported_devices: device_families(info),
+        required_capabilities: required_capabilities(info),
+        url_schemes: url_schemes(info),
+        ..Default::default()
+    };
+
+    let executable = plist_string(info, "CFBundleExecutable")
+        .unwrap_or_else(|| bundle.trim_end_matches(".app").to_string());
+    let binary_path = format!("{}{}", prefix, executable);
+    match zip.by_name(&binary_path) {
+        Ok(binary) => {
+            result.sizes.main_binary = binary.size();
+            match read_mach_o(binary) {
+                Ok(mach_o) => {
+                    result.architectures = mach_o.architectures;
+                    result.encrypted = mach_o.encrypted;
+                    result.entitlements = mach_o.entitlements;
+                }
+                Err(e) => log::warn!("Failed to parse main binary {}: {}", binary_path, e),
+            }
+        }
+        Err(e) => log::warn!("Main binary {} not found: {}", binary_path, e),
+    }
+
+    collect_components(&mut zip, &prefix, &executable, &mut result)?;
+    Ok(result)
+}
+
+fn device_families(info: &plist::Dictionary) -> Vec<String> {
+    let Some(families) = info.get("UIDeviceFamily").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    families
+        .iter()
+        .filter_map(|v| v.as_unsigned_integer())
+        .map(|family| match family {
+            1 => "iPhone".to_string(),
+            2 => "iPad".to_string(),
+            3 => "Apple TV".to_string(),
+            4 => "Apple Watch".to_string(),
+            7 => "Apple Vision".to_string(),
+            other => format!("family-{}", other),
+        })
+        .collect()
+}
+
+// UIRequiredDeviceCapabilities 可以是字符串数组，也可以是 { 能力: bool } 字典
+fn required_capabilities(info: &plist::Dictionary) -> Vec<String> {
+    match info.get("UIRequiredDeviceCapabilities") {
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(Value::as_string)
+            .map(str::to_string)
+            .collect(),
+        Some(Value::Dictionary(items)) => items
+            .iter()
+            .filter(|(_, required)| required.as_boolean() == Some(true))
+            .map(|(name, _)| name.clone())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn url_schemes(info: &plist::Dictionary) -> Vec<String> {
+    let Some(types) = info.get("CFBundleURLTypes").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    types
+        .iter()
+        .filter_map(Value::as_dictionary)
+        .filter_map(|t| t.get("CFBundleURLSchemes").and_then(Value::as_array))
+        .flatten()
+        .filter_map(Value::as_string)
+        .map(str::to_string)
+        .collect()
+}
+
+/// 按 `Frameworks/`、`PlugIns/` 统计各组件大小，并读取组件的 Info.plist
+fn collect_components<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    prefix: &str,
+    executable: &str,
+    result: &mut IpaInfo,
+) -> Result<(), IpaToolError> {
+    let mut frameworks: BTreeMap<String, BundleComponent> = BTreeMap::new();
+    let mut extensions: BTreeMap<String, BundleComponent> = BTreeMap::new();
+
+    for i in 0..zip.len() {
+        let entry = zip.by_index_raw(i)?;
+        let (size, compressed) = (entry.size(), entry.compressed_size());
+        result.sizes.total += size;
+        result.sizes.compressed_total += compressed;
+
+        let Some(relative) = entry.name().strip_prefix(prefix) else {
+            continue;
+        };
+        let component = if let Some(rest) = relative.strip_prefix("Frameworks/") {
+            component_name(rest).map(|name| frameworks.entry(name))
+        } else if let Some(rest) = relative.strip_prefix("PlugIns/") {
+            component_name(rest)
+                .filter(|name| name.ends_with(".appex"))
+                .map(|name| extensions.entry(name))
+        } else {
+            None
+        };
+
+        match component {
+            Some(component) => {
+                let component = component.or_insert_with_key(|name| BundleComponent {
+                    name: name.clone(),
+                    ..Default::default()
+                });
+                component.size += size;
+                component.compressed_size += compressed;
+            }
+            None if relative != executable => result.sizes.resources += size,
+            None => {}
+        }
+    }
+
+    for (dir, components) in [
+        ("Frameworks", &mut frameworks),
+        ("PlugIns", &mut extensions),
+    ] {
+        for component in components.values_mut() {
+            let plist_path = format!("{}{}/{}/Info.plist", prefix, dir, component.name);
+            let Ok(Value::Dictionary(info)) = read_plist_entry(zip, &plist_path) else {
+                continue;
+            };
+            component.bundle_id = plist_string(&info, "CFBundleIdentifier");
+            component.version = plist_string(&info, "CFBundleShortVersionString");
+            component.extension_point = info
+                .get("NSExtension")
+                .and_then(Value::as_dictionary)
+                .and_then(|ext| plist_string(ext, "NSExtensionPointIdentifier"))
+                .or_else(|| {
+                    // 新的 ExtensionKit 扩展使用 EXAppExtensionAttributes
+                    info.get("EXAppExtensionAttributes")
+                        .and_then(Value::as_dictionary)
+                        .and_then(|ext| plist_string(ext, "EXExtensionPointIdentifier"))
+                });
+        }
+    }
+
+    result.sizes.frameworks = frameworks.values().map(|c| c.size).sum();
+    result.sizes.extensions = extensions.values().map(|c| c.size).sum();
+    result.frameworks = frameworks.into_values().collect();
+    result.extensions = extensions.into_values().collect();
+    Ok(())
+}
+
+// `Foo.framework/Foo` -> `Foo.framework`，`libswiftCore.dylib` -> `libswiftCore.dylib`
+fn component_name(rest: &str) -> Option<String> {
+    let name = rest.split('/').next()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+struct MachOInfo {
+    architectures: Vec<String>,
+    encrypted: Option<bool>,
+    entitlements: Option<serde_json::Value>,
+}
+
+/// 顺序读取的流，记录当前位置，只能向前跳过
+struct StreamReader<R> {
+    inner: R,
+    pos: u64,
+}
+
+impl<R: Read> StreamReader<R> {
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, IpaToolError> {
+        let mut buffer = vec![0u8; len];
+        self.inner.read_exact(&mut buffer)?;
+        self.pos += len as u64;
+        Ok(buffer)
+    }
+
+    fn skip_to(&mut self, offset: u64) -> Result<(), IpaToolError> {
+        if offset < self.pos {
+            return Err(IpaToolError::Archive("Mach-O 偏移量无效".to_string()));
+        }
+        let skipped = std::io::copy(
+            &mut (&mut self.inner).take(offset - self.pos),
+            &mut std::io::sink(),
+        )?;
+        self.pos += skipped;
+        if self.pos != offset {
+            return Err(IpaToolError::Archive("Mach-O 文件被截断".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> u32 {
+    let raw: [u8; 4] = bytes[offset..offset + 4].try_into().unwrap();
+    if big_endian {
+        u32::from_be_bytes(raw)
+    } else {
+        u32::from_le_bytes(raw)
+    }
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_mach_o<R: Read>(reader: R) -> Result<MachOInfo, IpaToolError> {
+    let mut stream = StreamReader {
+        inner: reader,
+        pos: 0,
+    };
+    let header = stream.read_bytes(8)?;
+    let magic = read_u32(&header, 0, true);
+
+    // 通用二进制：列出所有架构，详细信息取 arm64 分片（没有时取第一个）
+    let (architectures, mut header) = if magic == FAT_MAGIC || magic == FAT_MAGIC_64 {
+        let count = read_u32(&header, 4, true) as usize;
+        let entry_size = if magic == FAT_MAGIC_64 { 32 } else { 20 };
+        let table = stream.read_bytes(count.min(64) * entry_size)?;
+        let slices: Vec<(u32, u32, u64)> = table
+            .chunks(entry_size)
+            .map(|entry| {
+                let offset = if magic == FAT_MAGIC_64 {
+                    read_u64(entry, 8)
+                } else {
+                    read_u32(entry, 8, true) as u64
+                };
+                (read_u32(entry, 0, true), read_u32(entry, 4, true), offset)
+            })
+            .collect();
+        let offset = slices
+            .iter()
+            .find(|(cpu, _, _)| *cpu == CPU_TYPE_ARM64)
+            .or(slices.first())
+            .map(|(_, _, offset)| *offset)
+            .ok_or_else(|| IpaToolError::Archive("通用二进制中没有架构".to_string()))?;
+        let names = slices
+            .iter()
+            .map(|(cpu, sub, _)| arch_name(*cpu, *sub))
+            .collect();
+        stream.skip_to(offset)?;
+        // 加载命令中的偏移量相对于分片起始位置
+        stream.pos = 0;
+        (Some(names), stream.read_bytes(8)?)
+    } else {
+        (None, header)
+    };
+
+    // 单一架构的 Mach-O 头（小端）
+    let magic = read_u32(&header, 0, false);
+    let header_size = match magic {
+        MH_MAGIC_64 => 32,
+        MH_MAGIC => 28,
+        _ => return Err(IpaToolError::Archive("不是有效的 Mach-O 文件".to_string())),
+    };
+    header.extend(stream.read_bytes(header_size - 8)?);
+    let cpu_type = read_u32(&header, 4, false);
+    let cpu_subtype = read_u32(&header, 8, false);
+    let ncmds = read_u32(&header, 16, false);
+    let sizeofcmds = read_u32(&header, 20, false);
+    if sizeofcmds > MAX_LOAD_COMMANDS_BYTES {
+        return Err(IpaToolError::Archive("Mach-O 加载命令过大".to_string()));
+    }
+
+    let commands = stream.read_bytes(sizeofcmds as usize)?;
+    let mut encrypted = None;
+    let mut code_signature = None;
+    let mut offset = 0usize;
+    for _ in 0..ncmds {
+        if offset + 8 > commands.len() {
+            break;
+        }
+        let cmd = read_u32(&commands, offset, false);
+        let cmdsize = read_u32(&commands, offset + 4, false) as usize;
+        if cmdsize < 8 || offset + cmdsize > commands.len() {
+            break;
+        }
+        match cmd {
+            LC_ENCRYPTION_INFO | LC_ENCRYPTION_INFO_64 if cmdsize >= 20 => {
+                let cryptid = read_u32(&commands, offset + 16, false);
+                encrypted = Some(encrypted.unwrap_or(false) || cryptid != 0);
+            }
+            LC_CODE_SIGNATURE if cmdsize >= 16 => {
+                let dataoff = read_u32(&commands, offset + 8, false) as u64;
+                let datasize = read_u32(&commands, offset + 12, false);
+                code_signature = Some((dataoff, datasize));
+            }
+            _ => {}
+        }
+        offset += cmdsize;
+    }
+
+    // 权限声明只是附加信息，签名数据损坏时不影响架构和加密状态的结果
+    let entitlements = code_signature.and_then(|(dataoff, datasize)| {
+        read_entitlements(&mut stream, dataoff, datasize).unwrap_or_else(|e| {
+            log::warn!("Failed to read entitlements: {}", e);
+            None
+        })
+    });
+
+    Ok(MachOInfo {
+        architectures: architectures.unwrap_or_else(|| vec![arch_name(cpu_type, cpu_subtype)]),
+        encrypted: encrypted.or(Some(false)),
+        entitlements,
+    })
+}
+
+/// 读取代码签名 SuperBlob 中的权限声明（XML plist）
+fn read_entitlements<R: Read>(
+    stream: &mut StreamReader<R>,
+    dataoff: u64,
+    datasize: u32,
+) -> Result<Option<serde_json::Value>, IpaToolError> {
+    stream.skip_to(dataoff)?;
+    let header = stream.read_bytes(12)?;
+    if read_u32(&header, 0, true) != CSMAGIC_EMBEDDED_SIGNATURE {
+        return Ok(None);
+    }
+    let count = read_u32(&header, 8, true).min(64) as usize;
+    let index = stream.read_bytes(count * 8)?;
+    let Some(blob_offset) = index
+        .chunks(8)
+        .find(|entry| read_u32(entry, 0, true) == CSSLOT_ENTITLEMENTS)
+        .map(|entry| read_u32(entry, 4, true))
+    else {
+        return Ok(None);
+    };
+    if blob_offset >= datasize {
+        return Ok(None);
+    }
+
+    stream.skip_to(dataoff + blob_offset as u64)?;
+    let blob_header = stream.read_bytes(8)?;
+    let length = read_u32(&blob_header, 4, true);
+    if read_u32(&blob_header, 0, true) != CSMAGIC_EMBEDDED_ENTITLEMENTS
+        || !(8..=MAX_ENTITLEMENTS_BYTES).contains(&length)
+    {
+        return Ok(None);
+    }
+    let xml = stream.read_bytes(length as usize - 8)?;
+    let value: Value = plist::from_bytes(&xml)?;
+    Ok(Some(serde_json::to_value(value)?))
+}
+
+fn arch_name(cpu_type: u32, cpu_subtype: u32) -> String {
+    let subtype = cpu_subtype & 0x00ff_ffff;
+    match (cpu_type, subtype) {
+        (CPU_TYPE_ARM64, 2) => "arm64e".to_string(),
+        (CPU_TYPE_ARM64, _) => "arm64".to_string(),
+        (0x0200_000c, _) => "arm64_32".to_string(),
+        (12, 11) => "armv7s".to_string(),
+        (12, 9) => "armv7".to_string(),
+        (12, _) => "arm".to_string(),
+        (0x0100_0007, _) => "x86_64".to_string(),
+        (7, _) => "i386".to_string(),
+        (other, _) => format!("cpu-{:#x}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // 构造一个 arm64 Mach-O：LC_ENCRYPTION_INFO_64(cryptid=1) + 带权限声明的代码签名
+    fn mach_o() -> Vec<u8> {
+        let mut entitlements = plist::Dictionary::new();
+        entitlements.insert(
+            "application-identifier".to_string(),
+            Value::String("TEAM.com.example.demo".to_string()),
+        );
+        let mut xml = Vec::new();
+        plist::to_writer_xml(&mut xml, &Value::Dictionary(entitlements)).unwrap();
+
+        let sizeofcmds = 24 + 16;
+        let dataoff = 32 + sizeofcmds + 8;
+        let mut signature = Vec::new();
+        let blob_offset = 12 + 8;
+        let blob_len = 8 + xml.len() as u32;
+        for v in [CSMAGIC_EMBEDDED_SIGNATURE, blob_offset + blob_len, 1] {
+            signature.extend(v.to_be_bytes());
+        }
+        signature.extend(CSSLOT_ENTITLEMENTS.to_be_bytes());
+        signature.extend(blob_offset.to_be_bytes());
+        signature.extend(CSMAGIC_EMBEDDED_ENTITLEMENTS.to_be_bytes());
+        signature.extend(blob_len.to_be_bytes());
+        signature.extend(&xml);
+
+        let mut binary = Vec::new();
+        for v in [MH_MAGIC_64, CPU_TYPE_ARM64, 0, 2, 2, sizeofcmds, 0, 0] {
+            binary.extend(v.to_le_bytes());
+        }
+        for v in [LC_ENCRYPTION_INFO_64, 24, 0x4000, 0x1000, 1, 0] {
+            binary.extend(v.to_le_bytes());
+        }
+        for v in [LC_CODE_SIGNATURE, 16, dataoff, signature.len() as u32] {
+            binary.extend(v.to_le_bytes());
+        }
+        binary.extend([0u8; 8]);
+        binary.extend(signature);
+        binary
+    }
+
+    fn plist_bytes(entries: &[(&str, Value)]) -> Vec<u8> {
+        let mut dict = plist::Dictionary::new();
+        for (key, value) in entries {
+            dict.insert(key.to_string(), value.clone());
+        }
+        let mut buffer = Vec::new();
+        plist::to_writer_xml(&mut buffer, &Value::Dictionary(dict)).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_inspect_ipa() {
+        let path = std::env::temp_dir()
+            .join(format!("ipa-inspect-{}.ipa", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let options: zip::write::FileOptions<'_, ()> = zip::write::FileOptions::default();
+        let string = |s: &str| Value::String(s.to_string());
+        let files: Vec<(&str, Vec<u8>)> = vec![
+            (
+                "Payload/Demo.app/Info.plist",
+                plist_bytes(&[
+                    ("CFBundleIdentifier", string("com.example.demo")),
+                    ("CFBundleExecutable", string("Demo")),
+                    ("CFBundleShortVersionString", string("2.1")),
+                    ("MinimumOSVersion", string("16.0")),
+                    ("UIDeviceFamily", Value::Array(vec![1.into(), 2.into()])),
+                    (
+                        "CFBundleURLTypes",
+                        Value::Array(vec![Value::Dictionary(
+                            [(
+                                "CFBundleURLSchemes".to_string(),
+                                Value::Array(vec![string("demo")]),
+                            )]
+                            .into_iter()
+                            .collect(),
+                        )]),
+                    ),
+                ]),
+            ),
+            ("Payload/Demo.app/Demo", mach_o()),
+            ("Payload/Demo.app/Assets.car", vec![1u8; 100]),
+            (
+                "Payload/Demo.app/Frameworks/Kit.framework/Kit",
+                vec![2u8; 50],
+            ),
+            (
+                "Payload/Demo.app/PlugIns/Widget.appex/Info.plist",
+                plist_bytes(&[
+                    ("CFBundleIdentifier", string("com.example.demo.widget")),
+                    (
+                        "NSExtension",
+                        Value::Dictionary(
+                            [(
+                                "NSExtensionPointIdentifier".to_string(),
+                                string("com.apple.widgetkit-extension"),
+                            )]
+                            .into_iter()
+                            .collect(),
+                        ),
+                    ),
+                ]),
+            ),
+        ];
+        for (name, content) in &files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let info = inspect_ipa(&path).unwrap();
+        assert_eq!(info.bundle_name, "Demo.app");
+        assert_eq!(info.bundle_id.as_deref(), Some("com.example.demo"));
+        assert_eq!(info.supported_devices, vec!["iPhone", "iPad"]);
+        assert_eq!(info.url_schemes, vec!["demo"]);
+        assert_eq!(info.architectures, vec!["arm64"]);
+        assert_eq!(info.encrypted, Some(true));
+        assert_eq!(
+            info.entitlements.unwrap()["application-identifier"],
+            "TEAM.com.example.demo"
+        );
+        assert_eq!(info.frameworks[0].name, "Kit.framework");
+        assert_eq!(info.frameworks[0].size, 50);
+        assert_eq!(
+            info.extensions[0].extension_point.as_deref(),
+            Some("com.apple.widgetkit-extension")
+        );
+        assert_eq!(info.sizes.main_binary, mach_o().len() as u64);
+        assert_eq!(info.sizes.frameworks, 50);
+        assert_eq!(
+            info.sizes.resources,
+            files[0].1.len() as u64 + 100,
+            "Info.plist + Assets.car"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_signature_keeps_mach_o_info() {
+        let mut binary = mach_o();
+        binary.truncate(binary.len() - 16);
+
+        let info = read_mach_o(binary.as_slice()).unwrap();
+        assert_eq!(info.architectures, vec!["arm64"]);
+        assert_eq!(info.encrypted, Some(true));
+        assert!(info.entitlements.is_none());
+    }
+}
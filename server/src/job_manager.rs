@@ -0,0 +1,553 @@
+use crate::account_registry::AccountRegistry;
+use crate::database::{Database, DownloadRecord};
+use crate::error::{ErrorCode, IpaToolError};
+use crate::file_naming::FileNaming;
+use crate::ipa_handler::{
+    DownloadMetadata, DownloadProgress, DownloadResult, ProgressSink, DEFAULT_DOWNLOAD_WORKERS,
+};
+use crate::messages::Locale;
+use crate::session_refresh::{DownloadOptions, SessionRefresher};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+
+// 已结束的任务在内存中保留的时间
+const FINISHED_JOB_RETENTION_HOURS: i64 = 24;
+// 每个任务事件通道的缓冲区大小，订阅者落后时只会丢失中间进度
+const JOB_EVENT_CAPACITY: usize = 64;
+// 下载记录进度写入数据库的最小间隔
+const PROGRESS_WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Ready,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobInfo {
+    pub job_id: String,
+    pub appid: String,
+    pub account_email: String,
+    pub status: JobStatus,
+    pub phase: Option<String>,
+    pub message: Option<String>,
+    pub percent: Option<f64>,
+    pub file_size: Option<u64>,
+    pub downloaded: Option<u64>,
+    /// 平均下载速度（字节/秒）
+    pub speed: Option<f64>,
+    /// 预计剩余时间（秒）
+    pub eta: Option<u64>,
+    pub file: Option<String>,
+    pub metadata: Option<DownloadMetadata>,
+    /// Apple 校验通过的 MD5（签名前）
+    pub md5: Option<String>,
+    /// 签名后最终文件的 MD5
+    pub signed_md5: Option<String>,
+    /// 文件是否通过了 Apple 校验和的校验
+    pub verified: bool,
+    pub error: Option<String>,
+    pub error_code: Option<ErrorCode>,
+    pub needs_purchase: bool,
+    pub needs_reauth: bool,
+    pub record_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// 推送给订阅者的任务事件
+#[derive(Debug, Clone)]
+pub enum JobEvent {
+    /// 进度更新，`line` 为本次进度的日志文案
+    Progress { job: JobInfo, line: String },
+    /// 任务已结束（成功或失败）
+    Finished(JobInfo),
+}
+
+/// 新建下载任务的参数
+#[derive(Debug, Clone)]
+pub struct JobRequest {
+    pub token: String,
+    pub appid: String,
+    pub app_ver_id: Option<String>,
+    pub auto_purchase: bool,
+    pub locale: Locale,
+}
+
+struct JobEntry {
+    info: JobInfo,
+    events: broadcast::Sender<JobEvent>,
+}
+
+type JobTable = Arc<Mutex<HashMap<String, JobEntry>>>;
+
+/// 后台下载任务管理：每个任务在独立的 tokio task 中运行，
+/// 状态保存在内存中，同时写入 `download_records`。
+#[derive(Clone)]
+pub struct JobManager {
+    jobs: JobTable,
+    accounts: Arc<AccountRegistry>,
+    sessions: SessionRefresher,
+    db: Arc<Database>,
+    download_dir: String,
+    workers: usize,
+    naming: FileNaming,
+    /// 所有任务共享的下载客户端
+    client: reqwest::Client,
+}
+
+impl JobManager {
+    pub fn new(
+        accounts: Arc<AccountRegistry>,
+        sessions: SessionRefresher,
+        db: Arc<Database>,
+        download_dir: &str,
+    ) -> Self {
+        Self {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            accounts,
+            sessions,
+            db,
+            download_dir: download_dir.to_string(),
+            workers: DEFAULT_DOWNLOAD_WORKERS,
+            naming: FileNaming::default(),
+            client: reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(30))
+                .build()
+                .unwrap(),
+        }
+    }
+
+    /// 设置每个任务同时下载的分块数
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// 设置输出文件的命名模板和碰撞策略
+    pub fn with_naming(mut self, naming: FileNaming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// 创建下载任务并在后台执行，返回任务 ID
+    pub async fn start(&self, request: JobRequest) -> Result<String, IpaToolError> {
+        let session = self
+            .accounts
+            .get_session(&request.token)
+            .await
+            .ok_or_else(|| IpaToolError::Session("无效的 token".to_string()))?;
+
+        let job_id = uuid::Uuid::new_v4().to_string();
+        let mut record = DownloadRecord {
+            id: None,
+            app_name: request.appid.clone(),
+            app_id: request.appid.clone(),
+            bundle_id: None,
+            version: None,
+            account_email: session.account.account_email.clone(),
+            account_region: Some(session.region.clone()),
+            download_date: None,
+            status: "downloading".to_string(),
+            file_size: None,
+            install_url: None,
+            artwork_url: None,
+            artist_name: None,
+            progress: Some(0),
+            error: None,
+            created_at: None,
+            md5: None,
+            file_path: None,
+            signed_md5: None,
+            verified: false,
+        };
+        record.id = match self.db.add_download_record(&record) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                log::warn!("Failed to create download record: {}", e);
+                None
+            }
+        };
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            prune_finished(&mut jobs);
+            let (events, _) = broadcast::channel(JOB_EVENT_CAPACITY);
+            let info = JobInfo {
+                job_id: job_id.clone(),
+                appid: request.appid.clone(),
+                account_email: record.account_email.clone(),
+                status: JobStatus::Running,
+                phase: None,
+                message: None,
+                percent: None,
+                file_size: None,
+                downloaded: None,
+                speed: None,
+                eta: None,
+                file: None,
+                metadata: None,
+                md5: None,
+                signed_md5: None,
+                verified: false,
+                error: None,
+                error_code: None,
+                needs_purchase: false,
+                needs_reauth: false,
+                record_id: record.id,
+                created_at: Utc::now(),
+                finished_at: None,
+            };
+            jobs.insert(job_id.clone(), JobEntry { info, events });
+        }
+
+        let (percent_tx, percent_rx) = watch::channel(0);
+        let writer = record
+            .id
+            .map(|record_id| spawn_progress_writer(self.db.clone(), record_id, percent_rx));
+
+        let options = DownloadOptions {
+            appid: request.appid,
+            app_ver_id: request.app_ver_id,
+            download_path: self.download_dir.clone(),
+            auto_purchase: request.auto_purchase,
+            locale: request.locale,
+            progress: Arc::new(JobProgress {
+                job_id: job_id.clone(),
+                jobs: self.jobs.clone(),
+                percent: percent_tx,
+                download_start: Mutex::new(None),
+            }),
+            workers: self.workers,
+            naming: self.naming.clone(),
+            client: self.client.clone(),
+        };
+
+        let manager = self.clone();
+        let token = request.token;
+        let id = job_id.clone();
+        tokio::spawn(async move {
+            let result = manager
+                .sessions
+                .download_with_reauth(&token, &options)
+                .await;
+            // 关闭进度通道并等待最后一次进度写入，避免覆盖最终记录
+            drop(options);
+            if let Some(writer) = writer {
+                let _ = writer.await;
+            }
+            let _ = tokio::task::spawn_blocking(move || manager.finish(&id, record, result)).await;
+        });
+
+        Ok(job_id)
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<JobInfo> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(job_id)
+            .map(|entry| entry.info.clone())
+    }
+
+    /// 订阅任务事件，同时返回订阅时的最新状态，供迟到的订阅者补发
+    pub fn subscribe(&self, job_id: &str) -> Option<(JobInfo, broadcast::Receiver<JobEvent>)> {
+        let jobs = self.jobs.lock().unwrap();
+        let entry = jobs.get(job_id)?;
+        Some((entry.info.clone(), entry.events.subscribe()))
+    }
+
+    fn finish(
+        &self,
+        job_id: &str,
+        mut record: DownloadRecord,
+        result: Result<DownloadResult, IpaToolError>,
+    ) {
+        let (job_status, error) = match &result {
+            Ok(result) if result.ok => (JobStatus::Ready, None),
+            Ok(result) => (
+                JobStatus::Failed,
+                Some(
+                    result
+                        .error
+                        .clone()
+                        .unwrap_or_else(|| IpaToolError::Store("下载失败".to_string())),
+                ),
+            ),
+            Err(e) => (JobStatus::Failed, Some(e.clone())),
+        };
+        let download = result.ok();
+
+        match &error {
+            None => log::info!("Download job {} finished", job_id),
+            Some(e) => log::warn!("Download job {} failed: {}", job_id, e),
+        }
+
+        // 先更新下载记录，订阅者收到结束事件时记录已是最终状态
+        if let Some(record_id) = record.id {
+            record.status = match job_status {
+                JobStatus::Ready => "completed",
+                _ => "failed",
+            }
+            .to_string();
+            record.error = error.as_ref().map(|e| e.to_string());
+            if let Some(metadata) = download.as_ref().and_then(|d| d.metadata.clone()) {
+                record.app_name = metadata.bundle_display_name;
+                record.bundle_id = Some(metadata.bundle_id);
+                record.version = Some(metadata.bundle_short_version_string);
+                record.artwork_url = Some(metadata.artwork_url);
+                record.artist_name = Some(metadata.artist_name);
+                record.progress = Some(100);
+            }
+            record.md5 = download.as_ref().and_then(|d| d.md5.clone());
+            record.signed_md5 = download.as_ref().and_then(|d| d.signed_md5.clone());
+            record.verified = download.as_ref().is_some_and(|d| d.verified);
+            record.file_path = download.as_ref().and_then(|d| d.file.clone());
+            record.file_size = self
+                .get(job_id)
+                .and_then(|job| job.file_size)
+                .map(|size| size as i64);
+
+            if let Err(e) = self.db.update_download_record(record_id, &record) {
+                log::warn!("Failed to update download record {}: {}", record_id, e);
+            }
+        }
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(entry) = jobs.get_mut(job_id) else {
+            return;
+        };
+        let job = &mut entry.info;
+        job.status = job_status;
+        job.finished_at = Some(Utc::now());
+        job.error = error.as_ref().map(|e| e.to_string());
+        job.error_code = error.as_ref().map(|e| e.code());
+        if let Some(download) = download {
+            job.file = download.file;
+            job.metadata = download.metadata;
+            job.md5 = download.md5;
+            job.signed_md5 = download.signed_md5;
+            job.verified = download.verified;
+            job.needs_purchase = download.needs_purchase;
+            job.needs_reauth = download.needs_reauth;
+        }
+        if job_status == JobStatus::Ready {
+            job.percent = Some(100.0);
+            job.eta = Some(0);
+        }
+        // 没有订阅者时发送失败，可以忽略
+        let _ = entry.events.send(JobEvent::Finished(job.clone()));
+    }
+}
+
+fn prune_finished(jobs: &mut HashMap<String, JobEntry>) {
+    let cutoff = Utc::now() - chrono::Duration::hours(FINISHED_JOB_RETENTION_HOURS);
+    jobs.retain(|_, entry| entry.info.finished_at.is_none_or(|at| at > cutoff));
+}
+
+/// 在后台把最新的百分比写入下载记录，每个间隔最多写一次
+///
+/// 进度回调只更新 watch 通道，SQLite 写入在阻塞线程池中执行；
+/// 发送端全部释放后写入剩余的值并结束。
+fn spawn_progress_writer(
+    db: Arc<Database>,
+    record_id: i64,
+    mut percent: watch::Receiver<i64>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while percent.changed().await.is_ok() {
+            let value = *percent.borrow_and_update();
+            let db = db.clone();
+            let result =
+                tokio::task::spawn_blocking(move || db.update_download_progress(record_id, value))
+                    .await;
+            if let Ok(Err(e)) = result {
+                log::warn!("Failed to update download progress: {}", e);
+            }
+            tokio::time::sleep(PROGRESS_WRITE_INTERVAL).await;
+        }
+    })
+}
+
+/// 将下载进度写入任务状态并推送给订阅者，百分比交给进度写入任务保存
+struct JobProgress {
+    job_id: String,
+    jobs: JobTable,
+    percent: watch::Sender<i64>,
+    // 下载阶段开始的时间和已下载字节数，用于计算平均速度
+    download_start: Mutex<Option<(Instant, u64)>>,
+}
+
+impl JobProgress {
+    /// 根据已下载字节数计算平均速度和剩余时间
+    fn speed_and_eta(&self, downloaded: u64, file_size: Option<u64>) -> (Option<f64>, Option<u64>) {
+        let mut start = self.download_start.lock().unwrap();
+        let (started_at, base) = *start.get_or_insert((Instant::now(), downloaded));
+        let elapsed = started_at.elapsed().as_secs_f64();
+        if elapsed <= 0.0 || downloaded <= base {
+            return (None, None);
+        }
+
+        let speed = (downloaded - base) as f64 / elapsed;
+        let eta =
+            file_size.map(|size| (size.saturating_sub(downloaded) as f64 / speed).ceil() as u64);
+        (Some(speed), eta)
+    }
+}
+
+impl ProgressSink for JobProgress {
+    fn report(&self, progress: DownloadProgress) {
+        let rate = progress
+            .downloaded
+            .map(|downloaded| self.speed_and_eta(downloaded, progress.file_size));
+
+        if let Some(entry) = self.jobs.lock().unwrap().get_mut(&self.job_id) {
+            let job = &mut entry.info;
+            job.phase = Some(progress.phase.clone());
+            job.message = Some(progress.message.clone());
+            if progress.progress.is_some() {
+                job.percent = progress.progress;
+            }
+            if progress.file_size.is_some() {
+                job.file_size = progress.file_size;
+            }
+            if progress.downloaded.is_some() {
+                job.downloaded = progress.downloaded;
+            }
+            if let Some((speed, eta)) = rate {
+                job.speed = speed;
+                job.eta = eta;
+            }
+            let _ = entry.events.send(JobEvent::Progress {
+                job: job.clone(),
+                line: progress.message.clone(),
+            });
+        }
+
+        if let Some(percent) = progress.progress {
+            // 百分比不变时不唤醒写入任务
+            self.percent.send_if_modified(|current| {
+                let changed = *current != percent as i64;
+                *current = percent as i64;
+                changed
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apple_auth::AccountStore;
+    use crate::crypto::EncryptionService;
+    use crate::key_manager::KeyManager;
+
+    fn manager() -> JobManager {
+        let dir = std::env::temp_dir().join(format!("ipa-jobs-{}", uuid::Uuid::new_v4()));
+        let db = Arc::new(Database::new(dir.join("test.db").to_str().unwrap()).unwrap());
+        let key_manager = Arc::new(KeyManager::with_database(db.clone()));
+        key_manager.init().unwrap();
+        let encryption = Arc::new(EncryptionService::new(key_manager));
+        let accounts =
+            Arc::new(AccountRegistry::new(Some(db.clone())).with_encryption(encryption.clone()));
+        let sessions = SessionRefresher::new(accounts.clone(), encryption, db.clone());
+        JobManager::new(accounts, sessions, db, dir.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_token_rejected() {
+        let manager = manager();
+        let err = manager
+            .start(JobRequest {
+                token: "unknown".to_string(),
+                appid: "1".to_string(),
+                app_ver_id: None,
+                auto_purchase: false,
+                locale: Locale::default(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::SessionInvalid);
+    }
+
+    #[tokio::test]
+    async fn test_failed_job_recorded() {
+        let manager = manager();
+        // 未认证的账号会在下载前失败
+        let token = manager
+            .accounts
+            .insert(AccountStore::new("user@example.com"), "US")
+            .await;
+
+        let job_id = manager
+            .start(JobRequest {
+                token,
+                appid: "1".to_string(),
+                app_ver_id: None,
+                auto_purchase: false,
+                locale: Locale::default(),
+            })
+            .await
+            .unwrap();
+
+        // 订阅时任务可能已经结束，此时快照即为最终状态
+        let (mut job, mut events) = manager.subscribe(&job_id).unwrap();
+        while job.status == JobStatus::Running {
+            if let JobEvent::Finished(finished) = events.recv().await.unwrap() {
+                job = finished;
+            }
+        }
+
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.error_code, Some(ErrorCode::SessionInvalid));
+        let records = manager.db.get_all_download_records().unwrap();
+        assert_eq!(records[0].status, "failed");
+        assert_eq!(records[0].id, job.record_id);
+    }
+
+    #[tokio::test]
+    async fn test_progress_writer_keeps_latest_percent() {
+        let manager = manager();
+        let record = DownloadRecord {
+            id: None,
+            app_name: "1".to_string(),
+            app_id: "1".to_string(),
+            bundle_id: None,
+            version: None,
+            account_email: "user@example.com".to_string(),
+            account_region: None,
+            download_date: None,
+            status: "downloading".to_string(),
+            file_size: None,
+            install_url: None,
+            artwork_url: None,
+            artist_name: None,
+            progress: Some(0),
+            error: None,
+            created_at: None,
+            md5: None,
+            file_path: None,
+            signed_md5: None,
+            verified: false,
+        };
+        let record_id = manager.db.add_download_record(&record).unwrap();
+
+        let (tx, rx) = watch::channel(0);
+        let writer = spawn_progress_writer(manager.db.clone(), record_id, rx);
+        for percent in [10, 20, 30] {
+            tx.send(percent).unwrap();
+        }
+        drop(tx);
+        writer.await.unwrap();
+
+        let record = manager.db.get_download_record(record_id).unwrap().unwrap();
+        assert_eq!(record.progress, Some(30));
+    }
+}
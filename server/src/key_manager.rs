@@ -1,6 +1,9 @@
+use crate::database::{Database, EncryptionKey};
+use crate::error::IpaToolError;
+use crate::master_key::{is_wrapped, unwrap_key, wrap_key, MasterKey};
 use rand::Rng;
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 const KEY_ROTATION_INTERVAL_MS: i64 = 30 * 24 * 60 * 60 * 1000;
@@ -11,6 +14,8 @@ pub struct KeyManager {
     last_rotation: Mutex<Option<i64>>,
     next_rotation: Mutex<Option<i64>>,
     previous_keys: Mutex<HashMap<String, String>>,
+    db: Option<Arc<Database>>,
+    master_key: Option<MasterKey>,
 }
 
 impl KeyManager {
@@ -21,10 +26,62 @@ impl KeyManager {
             last_rotation: Mutex::new(None),
             next_rotation: Mutex::new(None),
             previous_keys: Mutex::new(HashMap::new()),
+            db: None,
+            master_key: None,
         }
     }
 
-    pub fn init(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// 创建一个将密钥持久化到 `encryption_keys` 表的 KeyManager
+    pub fn with_database(db: Arc<Database>) -> Self {
+        Self {
+            db: Some(db),
+            ..Self::new()
+        }
+    }
+
+    /// 设置用于包装持久化数据密钥的 KEK
+    pub fn with_master_key(mut self, master_key: Option<MasterKey>) -> Self {
+        self.master_key = master_key;
+        self
+    }
+
+    pub fn init(&self) -> Result<(), IpaToolError> {
+        if let Some(db) = &self.db {
+            let keys = db.get_all_encryption_keys()?;
+
+            // 配置了 KEK 时，把尚未包装的旧密钥就地包装
+            if let Some(master_key) = &self.master_key {
+                for key in keys.iter().filter(|k| !is_wrapped(&k.key_value)) {
+                    log::info!("Wrapping encryption key {} with master key", key.key_id);
+                    db.update_encryption_key_value(&key.key_id, &master_key.wrap(&key.key_value)?)?;
+                }
+            } else if !keys.is_empty() {
+                log::warn!("No master key configured, encryption keys are stored unwrapped");
+            }
+
+            self.load_keys(keys)?;
+        }
+
+        if self.current_key.lock().unwrap().is_none() {
+            self.rotate_key()?;
+        }
+        Ok(())
+    }
+
+    fn load_keys(&self, keys: Vec<EncryptionKey>) -> Result<(), IpaToolError> {
+        let mut previous_keys = self.previous_keys.lock().unwrap();
+        for key in keys {
+            let key_value = unwrap_key(self.master_key.as_ref(), &key.key_value)
+                .map_err(|e| e.context(&format!("Failed to load encryption key {}", key.key_id)))?;
+            if key.is_current {
+                *self.current_key.lock().unwrap() = Some(key_value);
+                *self.current_key_id.lock().unwrap() = Some(key.key_id);
+                *self.last_rotation.lock().unwrap() = Some(key.last_rotation);
+                *self.next_rotation.lock().unwrap() = Some(key.next_rotation);
+            } else {
+                previous_keys.insert(key.key_id, key_value);
+            }
+        }
         Ok(())
     }
 
@@ -56,10 +113,31 @@ impl KeyManager {
         }
     }
 
-    pub fn rotate_key(&self) -> Result<KeyInfo, Box<dyn std::error::Error>> {
+    pub fn rotate_key(&self) -> Result<KeyInfo, IpaToolError> {
         let mut previous_keys = self.previous_keys.lock().unwrap();
         let mut current_key_id = self.current_key_id.lock().unwrap();
 
+        let new_key = self.generate_new_key();
+        let new_key_id = self.generate_key_id();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| IpaToolError::Crypto(e.to_string()))?
+            .as_millis() as i64;
+        let next = now + KEY_ROTATION_INTERVAL_MS;
+
+        // 先持久化新密钥，失败时内存中的状态保持不变
+        if let Some(db) = &self.db {
+            db.save_encryption_key(&EncryptionKey {
+                id: None,
+                key_id: new_key_id.clone(),
+                key_value: wrap_key(self.master_key.as_ref(), &new_key)?,
+                is_current: true,
+                created_at: None,
+                last_rotation: now,
+                next_rotation: next,
+            })?;
+        }
+
         if let Some(old_key_id) = &*current_key_id {
             let mut current_key = self.current_key.lock().unwrap();
             if let Some(old_key) = current_key.take() {
@@ -67,11 +145,6 @@ impl KeyManager {
             }
         }
 
-        let new_key = self.generate_new_key();
-        let new_key_id = self.generate_key_id();
-        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
-        let next = now + KEY_ROTATION_INTERVAL_MS;
-
         {
             let mut current_key = self.current_key.lock().unwrap();
             *current_key = Some(new_key.clone());
@@ -97,20 +170,56 @@ impl KeyManager {
         })
     }
 
-    pub fn get_current_key(&self) -> Result<String, Box<dyn std::error::Error>> {
+    pub fn get_current_key(&self) -> Result<String, IpaToolError> {
         let current_key = self.current_key.lock().unwrap();
         match &*current_key {
             Some(key) => Ok(key.clone()),
-            None => Err("Encryption key not initialized".into()),
+            None => Err(IpaToolError::Crypto(
+                "Encryption key not initialized".to_string(),
+            )),
         }
     }
 
-    pub fn get_current_key_id(&self) -> Result<String, Box<dyn std::error::Error>> {
+    pub fn get_current_key_id(&self) -> Result<String, IpaToolError> {
         let current_key_id = self.current_key_id.lock().unwrap();
         match &*current_key_id {
             Some(key_id) => Ok(key_id.clone()),
-            None => Err("Encryption key ID not initialized".into()),
+            None => Err(IpaToolError::Crypto(
+                "Encryption key ID not initialized".to_string(),
+            )),
+        }
+    }
+
+    pub fn get_key_by_id(&self, key_id: &str) -> Result<String, IpaToolError> {
+        if self.current_key_id.lock().unwrap().as_deref() == Some(key_id) {
+            return self.get_current_key();
+        }
+        match self.previous_keys.lock().unwrap().get(key_id) {
+            Some(key) => Ok(key.clone()),
+            None => Err(IpaToolError::Crypto(format!(
+                "Encryption key not found: {}",
+                key_id
+            ))),
+        }
+    }
+
+    pub fn previous_key_ids(&self) -> Vec<String> {
+        self.previous_keys.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// 删除一个旧密钥，调用前需确保已没有凭证使用该密钥
+    pub fn retire_key(&self, key_id: &str) -> Result<(), IpaToolError> {
+        if self.current_key_id.lock().unwrap().as_deref() == Some(key_id) {
+            return Err(IpaToolError::Crypto(
+                "Cannot retire the current encryption key".to_string(),
+            ));
+        }
+
+        if let Some(db) = &self.db {
+            db.delete_encryption_key(key_id)?;
         }
+        self.previous_keys.lock().unwrap().remove(key_id);
+        Ok(())
     }
 
     pub fn get_key_info(&self) -> KeyInfo {
@@ -127,7 +236,7 @@ impl KeyManager {
         }
     }
 
-    pub fn manual_rotate(&self) -> Result<KeyInfo, Box<dyn std::error::Error>> {
+    pub fn manual_rotate(&self) -> Result<KeyInfo, IpaToolError> {
         self.rotate_key()
     }
 }
@@ -163,4 +272,44 @@ mod tests {
         let key_id = manager.generate_key_id();
         assert!(key_id.starts_with("key-"));
     }
+
+    #[test]
+    fn test_keys_survive_restart() {
+        let dir = std::env::temp_dir().join(format!("ipa-keys-{}", uuid::Uuid::new_v4()));
+        let db_path = dir.join("test.db");
+        let db = Arc::new(Database::new(db_path.to_str().unwrap()).unwrap());
+
+        let manager = KeyManager::with_database(db.clone());
+        manager.init().unwrap();
+        let old_key_id = manager.get_current_key_id().unwrap();
+        let old_key = manager.get_current_key().unwrap();
+        let new_info = manager.rotate_key().unwrap();
+
+        let restarted = KeyManager::with_database(db);
+        restarted.init().unwrap();
+        assert_eq!(restarted.get_current_key_id().unwrap(), new_info.key_id);
+        assert_eq!(restarted.get_key_by_id(&old_key_id).unwrap(), old_key);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_keys_wrapped_with_master_key() {
+        let dir = std::env::temp_dir().join(format!("ipa-keys-{}", uuid::Uuid::new_v4()));
+        let db = Arc::new(Database::new(dir.join("test.db").to_str().unwrap()).unwrap());
+        let master_key = MasterKey::from_hex(&hex::encode([9u8; 32])).unwrap();
+
+        let manager = KeyManager::with_database(db.clone()).with_master_key(Some(master_key));
+        manager.init().unwrap();
+        let key = manager.get_current_key().unwrap();
+
+        let stored = db.get_current_encryption_key().unwrap().unwrap();
+        assert!(is_wrapped(&stored.key_value));
+        assert!(!stored.key_value.contains(&key));
+
+        // 缺少 KEK 时无法加载
+        assert!(KeyManager::with_database(db).init().is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
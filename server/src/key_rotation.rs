@@ -0,0 +1,207 @@
+use crate::crypto::{EncryptedPayload, EncryptionService};
+use crate::database::Database;
+use crate::error::IpaToolError;
+use crate::key_manager::KeyInfo;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RETIRED_KEY_GRACE_PERIOD_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RotationStatus {
+    pub last_check: Option<i64>,
+    pub last_rotation: Option<i64>,
+    pub reencrypted: usize,
+    pub pruned_keys: Vec<String>,
+    pub last_error: Option<String>,
+}
+
+/// 定期检查密钥是否到期，到期后轮换密钥、重新加密凭证，并在宽限期后清理旧密钥
+pub struct KeyRotationScheduler {
+    encryption: Arc<EncryptionService>,
+    db: Arc<Database>,
+    status: Mutex<RotationStatus>,
+}
+
+impl KeyRotationScheduler {
+    pub fn new(encryption: Arc<EncryptionService>, db: Arc<Database>) -> Self {
+        Self {
+            encryption,
+            db,
+            status: Mutex::new(RotationStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> RotationStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn spawn(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ROTATION_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let scheduler = self.clone();
+                let _ = tokio::task::spawn_blocking(move || scheduler.run_once()).await;
+            }
+        })
+    }
+
+    /// 执行一次检查：需要时轮换密钥，然后清理过期的旧密钥
+    pub fn run_once(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.last_check = Some(now_ms());
+
+        if self.encryption.key_manager().needs_rotation() {
+            log::info!("Encryption key expired, rotating");
+            if let Err(e) = self.rotate_locked(&mut status, false) {
+                log::error!("Automatic key rotation failed: {}", e);
+                status.last_error = Some(e.to_string());
+                return;
+            }
+        }
+
+        match self.prune_retired_keys() {
+            Ok(pruned) => {
+                if !pruned.is_empty() {
+                    log::info!("Pruned retired encryption keys: {:?}", pruned);
+                }
+                status.pruned_keys.extend(pruned);
+            }
+            Err(e) => {
+                log::error!("Failed to prune retired keys: {}", e);
+                status.last_error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// 立即轮换密钥（管理接口触发）
+    pub fn rotate_now(&self) -> Result<KeyInfo, IpaToolError> {
+        let mut status = self.status.lock().unwrap();
+        self.rotate_locked(&mut status, true)
+    }
+
+    fn rotate_locked(
+        &self,
+        status: &mut RotationStatus,
+        manual: bool,
+    ) -> Result<KeyInfo, IpaToolError> {
+        let key_manager = self.encryption.key_manager();
+        let info = if manual {
+            key_manager.manual_rotate()
+        } else {
+            key_manager.rotate_key()
+        }?;
+
+        status.last_rotation = Some(info.last_rotation);
+        status.last_error = None;
+
+        match self.encryption.reencrypt_credentials(&self.db) {
+            Ok(count) => {
+                log::info!(
+                    "Rotated to key {}, re-encrypted {} credentials",
+                    info.key_id,
+                    count
+                );
+                status.reencrypted = count;
+                Ok(info)
+            }
+            Err(e) => {
+                // 新密钥已生效，旧密钥仍保留，下一次检查时会继续迁移
+                let e = e.context("Failed to re-encrypt credentials");
+                status.last_error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    fn prune_retired_keys(&self) -> Result<Vec<String>, IpaToolError> {
+        let now = now_ms();
+        let key_manager = self.encryption.key_manager();
+        let mut in_use: HashSet<String> = self
+            .db
+            .get_all_credentials()?
+            .into_iter()
+            .map(|c| c.key_id)
+            .collect();
+        for account in self.db.get_all_accounts()? {
+            if let Some(sealed) = account.auth_info {
+                let payload: EncryptedPayload = serde_json::from_str(&sealed)?;
+                in_use.insert(payload.key_id);
+            }
+        }
+
+        let mut keys = self.db.get_all_encryption_keys()?;
+        keys.sort_by_key(|k| k.last_rotation);
+
+        let mut pruned = Vec::new();
+        for pair in keys.windows(2) {
+            let (old, successor) = (&pair[0], &pair[1]);
+            if old.is_current || in_use.contains(&old.key_id) {
+                continue;
+            }
+            // 旧密钥自被替换起保留一个宽限期
+            if now - successor.last_rotation >= RETIRED_KEY_GRACE_PERIOD_MS {
+                key_manager.retire_key(&old.key_id)?;
+                pruned.push(old.key_id.clone());
+            }
+        }
+
+        Ok(pruned)
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::EncryptionKey;
+    use crate::key_manager::KeyManager;
+
+    #[test]
+    fn test_prune_respects_grace_period_and_usage() {
+        let dir = std::env::temp_dir().join(format!("ipa-rotation-{}", uuid::Uuid::new_v4()));
+        let db = Arc::new(Database::new(dir.join("test.db").to_str().unwrap()).unwrap());
+        let now = now_ms();
+        let key = |id: &str, is_current, last_rotation| EncryptionKey {
+            id: None,
+            key_id: id.to_string(),
+            key_value: hex::encode([7u8; 32]),
+            is_current,
+            created_at: None,
+            last_rotation,
+            next_rotation: last_rotation + 1,
+        };
+        let month = 30 * 24 * 60 * 60 * 1000;
+        db.save_encryption_key(&key("old", false, now - 3 * month))
+            .unwrap();
+        db.save_encryption_key(&key("recent", false, now - 2 * month))
+            .unwrap();
+        db.save_encryption_key(&key("current", true, now - 1000))
+            .unwrap();
+
+        let key_manager = Arc::new(KeyManager::with_database(db.clone()));
+        key_manager.init().unwrap();
+        let scheduler = KeyRotationScheduler::new(
+            Arc::new(EncryptionService::new(key_manager.clone())),
+            db.clone(),
+        );
+
+        // "old" 已过宽限期，"recent" 刚被替换不久
+        assert_eq!(scheduler.prune_retired_keys().unwrap(), vec!["old"]);
+        assert!(key_manager.get_key_by_id("old").is_err());
+        assert!(key_manager.get_key_by_id("recent").is_ok());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
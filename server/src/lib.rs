@@ -1,14 +1,45 @@
+pub mod account_registry;
 pub mod apple_auth;
+pub mod crypto;
 pub mod database;
+pub mod download_manifest;
+pub mod download_root;
+pub mod error;
+pub mod file_naming;
 pub mod ipa_handler;
+pub mod ipa_inspector;
+pub mod job_manager;
 pub mod key_manager;
+pub mod key_rotation;
+pub mod master_key;
+pub mod messages;
+pub mod pending_auth;
+pub mod plist_codec;
+pub mod session_refresh;
 pub mod signature;
+pub mod store_models;
 
+pub use account_registry::{AccountRegistry, SessionHealth};
 pub use apple_auth::{AccountStore, AuthInfo, Store};
-pub use database::Database;
+pub use crypto::EncryptionService;
+pub use database::{Database, DownloadRecord};
+pub use download_manifest::DownloadManifest;
+pub use download_root::{filename_from_url, DownloadRoot};
+pub use error::{ErrorCode, IpaToolError};
+pub use file_naming::{CollisionPolicy, FileNaming};
 pub use ipa_handler::{
     download_ipa_with_account, get_license_error_message, DownloadMetadata, DownloadProgress,
-    DownloadResult,
+    DownloadResult, NoopProgress, ProgressSink, DEFAULT_DOWNLOAD_WORKERS,
 };
+pub use ipa_inspector::{inspect_ipa, BundleComponent, ComponentSizes, IpaInfo};
+pub use job_manager::{JobEvent, JobInfo, JobManager, JobRequest, JobStatus};
 pub use key_manager::KeyManager;
-pub use signature::{read_zip, SignatureClient};
+pub use key_rotation::KeyRotationScheduler;
+pub use master_key::MasterKey;
+pub use messages::{Locale, Message};
+pub use session_refresh::{DownloadOptions, RefreshError, SessionRefresher};
+pub use signature::{read_ipa_metadata, read_zip, IpaMetadata, SignatureClient};
+pub use store_models::{
+    AppMetadata, DownloadProductResponse, FailureKind, FailureResponse, SinfEntry, SongListItem,
+    StoreResponse,
+};
@@ -1,17 +1,28 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use ipa_webtool_services::{AccountStore, Database};
+use actix_web::{http::header, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use futures::StreamExt;
+use ipa_webtool_services::apple_auth::is_mfa_required;
+use ipa_webtool_services::master_key::rewrap_encryption_keys;
+use ipa_webtool_services::pending_auth::{PendingAuthStore, PendingLogin};
+use ipa_webtool_services::{
+    filename_from_url, get_license_error_message, inspect_ipa, read_ipa_metadata, AccountRegistry,
+    AccountStore, CollisionPolicy, Database, DownloadRoot, EncryptionService, ErrorCode,
+    FailureKind, FileNaming, IpaToolError, JobEvent, JobInfo, JobManager, JobRequest, JobStatus,
+    KeyManager, KeyRotationScheduler, Locale, MasterKey, RefreshError, SessionHealth,
+    SessionRefresher, StoreResponse, DEFAULT_DOWNLOAD_WORKERS,
+};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
-use std::sync::Mutex;
-use tokio::sync::RwLock;
+use std::sync::Arc;
 
 #[derive(Serialize)]
 struct ApiResponse<T> {
     ok: bool,
     data: Option<T>,
     error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
 }
 
 impl<T> ApiResponse<T> {
@@ -20,14 +31,24 @@ impl<T> ApiResponse<T> {
             ok: true,
             data: Some(data),
             error: None,
+            code: None,
         }
     }
 
-    fn error(error: String) -> Self {
+    fn error(error: IpaToolError) -> Self {
         Self {
             ok: false,
             data: None,
-            error: Some(error),
+            error: Some(error.to_string()),
+            code: Some(error.code()),
+        }
+    }
+
+    /// 错误响应附带客户端继续操作所需的数据（例如 MFA 的 challengeId）
+    fn error_with_data(error: IpaToolError, data: T) -> Self {
+        Self {
+            data: Some(data),
+            ..Self::error(error)
         }
     }
 }
@@ -63,22 +84,84 @@ struct DownloadRequest {
 }
 
 #[derive(Deserialize)]
+#[allow(non_snake_case)]
 struct LoginRequest {
     email: String,
     password: String,
+    #[serde(alias = "code")]
     mfa: Option<String>,
+    #[serde(default)]
+    saveCredentials: bool,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct MfaRequest {
+    challengeId: String,
+    #[serde(alias = "mfa")]
+    code: String,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct StartDownloadRequest {
+    token: String,
+    appid: String,
+    appVerId: Option<String>,
+    #[serde(default)]
+    autoPurchase: bool,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JobQuery {
+    jobId: String,
+}
+
+#[derive(Deserialize)]
+struct RefreshRequest {
+    token: String,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct DeleteAccountQuery {
+    #[serde(default)]
+    deleteCredentials: bool,
+}
+
+#[derive(Deserialize)]
+struct CredentialsRequest {
+    email: String,
+    password: String,
 }
 
 // 应用状态
-#[allow(dead_code)]
 struct AppState {
-    db: Mutex<Database>,
-    accounts: RwLock<HashMap<String, AccountStore>>, // token -> AccountStore
+    db: Arc<Database>,
+    accounts: Arc<AccountRegistry>,
+    encryption: Arc<EncryptionService>,
+    key_rotation: Arc<KeyRotationScheduler>,
+    sessions: SessionRefresher,
+    pending_logins: Arc<PendingAuthStore>,
+    jobs: JobManager,
+    downloads: DownloadRoot,
 }
 
-// 模拟的账号存储（生产环境应该使用数据库）
-lazy_static::lazy_static! {
-    static ref ACCOUNTS: RwLock<HashMap<String, AccountStore>> = RwLock::new(HashMap::new());
+// 请求语言：优先使用 ?lang= 参数，其次是 Accept-Language 头
+fn request_locale(req: &HttpRequest) -> Locale {
+    let from_query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.get("lang").and_then(|lang| Locale::parse(lang)));
+
+    from_query
+        .or_else(|| {
+            req.headers()
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(Locale::from_accept_language)
+        })
+        .unwrap_or_default()
 }
 
 // 健康检查
@@ -171,84 +254,78 @@ async fn get_versions(query: web::Query<VersionQuery>) -> impl Responder {
 }
 
 // 获取下载链接
-async fn get_download_url(query: web::Query<DownloadUrlQuery>) -> impl Responder {
-    let accounts = ACCOUNTS.read().await;
-    let account_store = accounts.get(&query.token);
-
-    if account_store.is_none() {
-        return HttpResponse::Unauthorized()
-            .json(ApiResponse::<String>::error("无效的 token".to_string()));
-    }
-
-    let account_store = account_store.unwrap();
+async fn get_download_url(
+    req: HttpRequest,
+    query: web::Query<DownloadUrlQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let locale = request_locale(&req);
+    let account_store = match data.accounts.get(&query.token).await {
+        Some(account_store) => account_store,
+        None => {
+            return HttpResponse::Unauthorized().json(ApiResponse::<String>::error(
+                IpaToolError::Session("无效的 token".to_string()),
+            ))
+        }
+    };
 
     // 调用 download_product
-    match account_store
+    let mut download_result = account_store
         .download_product(&query.appid, query.appVerId.as_deref())
-        .await
-    {
-        Ok(result) => {
-            let state = result
-                .get("_state")
-                .and_then(|v| v.as_str())
-                .unwrap_or("failure");
+        .await;
 
-            if state == "success" {
-                // 提取下载链接
-                if let Some(song_list) = result.get("songList").and_then(|sl| sl.as_array()) {
-                    if let Some(first_song) = song_list.first() {
-                        if let Some(url) = first_song.get("URL").and_then(|u| u.as_str()) {
-                            // 提取元数据
-                            let metadata = first_song.get("metadata").and_then(|m| m.as_object());
-
-                            return HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
-                                "url": url,
-                                "fileName": format!("{}_{}.ipa",
-                                    metadata.and_then(|m| m.get("bundleDisplayName")).and_then(|v| v.as_str()).unwrap_or("app"),
-                                    metadata.and_then(|m| m.get("bundleShortVersionString")).and_then(|v| v.as_str()).unwrap_or("1.0.0")
-                                ),
-                                "metadata": {
-                                    "bundle_display_name": metadata.and_then(|m| m.get("bundleDisplayName")).and_then(|v| v.as_str()).unwrap_or(""),
-                                    "bundle_short_version_string": metadata.and_then(|m| m.get("bundleShortVersionString")).and_then(|v| v.as_str()).unwrap_or(""),
-                                    "bundle_id": metadata.and_then(|m| m.get("bundleId")).and_then(|v| v.as_str()).unwrap_or(""),
-                                    "artwork_url": metadata.and_then(|m| m.get("artworkUrl")).and_then(|v| v.as_str()).unwrap_or(""),
-                                    "artist_name": metadata.and_then(|m| m.get("artistName")).and_then(|v| v.as_str()).unwrap_or(""),
-                                }
-                            })));
-                        }
-                    }
-                }
-
-                HttpResponse::BadRequest()
-                    .json(ApiResponse::<String>::error("无法获取下载链接".to_string()))
-            } else {
-                // 检查是否需要购买
-                let error_msg = result
-                    .get("customerMessage")
-                    .or(result.get("failureType"))
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("下载失败");
-
-                let is_license_error = error_msg.to_lowercase().contains("license")
-                    || error_msg.to_lowercase().contains("not found")
-                    || error_msg.contains("未购买");
-
-                if is_license_error {
-                    HttpResponse::BadRequest().json(serde_json::json!({
-                        "ok": false,
-                        "needsPurchase": true,
-                        "error": error_msg
-                    }))
-                } else {
-                    HttpResponse::BadRequest()
-                        .json(ApiResponse::<String>::error(error_msg.to_string()))
-                }
+    // 会话失效时使用保存的凭证重新认证并重试一次
+    let session_expired = matches!(
+        &download_result,
+        Ok(StoreResponse::Failure(failure)) if failure.kind().needs_reauth()
+    );
+    if session_expired {
+        match data.sessions.refresh(&query.token).await {
+            Ok(refreshed) => {
+                download_result = refreshed
+                    .download_product(&query.appid, query.appVerId.as_deref())
+                    .await;
             }
+            Err(e) => log::warn!("自动重新认证失败: {}", e),
         }
-        Err(e) => HttpResponse::InternalServerError().json(ApiResponse::<String>::error(format!(
-            "获取下载链接失败: {}",
-            e
-        ))),
+    }
+
+    match download_result {
+        Ok(StoreResponse::Success(product)) => match product.song_list.first() {
+            Some(song) => {
+                let metadata = &song.metadata;
+                HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+                    "url": song.url,
+                    "fileName": format!("{}_{}.ipa",
+                        metadata.bundle_display_name.as_deref().unwrap_or("app"),
+                        metadata.bundle_short_version_string.as_deref().unwrap_or("1.0.0")
+                    ),
+                    "metadata": {
+                        "bundle_display_name": metadata.bundle_display_name.as_deref().unwrap_or(""),
+                        "bundle_short_version_string": metadata.bundle_short_version_string.as_deref().unwrap_or(""),
+                        "bundle_id": metadata.bundle_id.as_deref().unwrap_or(""),
+                        "artwork_url": metadata.artwork().unwrap_or(""),
+                        "artist_name": metadata.artist_name.as_deref().unwrap_or(""),
+                    }
+                })))
+            }
+            None => HttpResponse::BadRequest().json(ApiResponse::<String>::error(
+                IpaToolError::InvalidResponse("无法获取下载链接".to_string()),
+            )),
+        },
+        Ok(StoreResponse::Failure(failure)) => match failure.kind() {
+            // 需要购买
+            FailureKind::LicenseRequired => HttpResponse::BadRequest().json(serde_json::json!({
+                "ok": false,
+                "needsPurchase": true,
+                "error": get_license_error_message(&failure, locale),
+                "code": ErrorCode::LicenseRequired,
+            })),
+            _ => HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(IpaToolError::from(&failure))),
+        },
+        Err(e) => HttpResponse::InternalServerError()
+            .json(ApiResponse::<String>::error(e.context("获取下载链接失败"))),
     }
 }
 
@@ -258,79 +335,105 @@ async fn download_ipa(
     data: web::Data<AppState>,
 ) -> impl Responder {
     // 验证 token
-    let accounts = data.accounts.read().await;
-    let _account_store = accounts.get(&req.token);
-
-    if _account_store.is_none() {
-        return HttpResponse::Unauthorized()
-            .json(ApiResponse::<String>::error("无效的 token".to_string()));
-    }
-
-    drop(accounts);
-
-    // 创建下载目录
-    let download_dir = "../downloads";
-    if tokio::fs::create_dir_all(download_dir).await.is_err() {
-        return HttpResponse::InternalServerError()
-            .json(ApiResponse::<String>::error("创建下载目录失败".to_string()));
+    if data.accounts.get(&req.token).await.is_none() {
+        return HttpResponse::Unauthorized().json(ApiResponse::<String>::error(
+            IpaToolError::Session("无效的 token".to_string()),
+        ));
     }
 
-    // 获取下载 URL
-    let url = &req.url;
-
-    // 解析 URL 获取文件名
-    let filename = url.split("/").last().unwrap_or("app.ipa");
-    let filepath = format!("{}/{}", download_dir, filename);
+    // 下载路径和文件名都限制在下载根目录内
+    let filepath = match data
+        .downloads
+        .resolve_dir(req.downloadPath.as_deref())
+        .await
+        .and_then(|dir| {
+            data.downloads
+                .resolve_file(&dir, &filename_from_url(&req.url))
+        }) {
+        Ok(path) => path,
+        Err(e @ IpaToolError::InvalidRequest(_)) => {
+            return HttpResponse::BadRequest().json(ApiResponse::<String>::error(e))
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(e.context("创建下载目录失败")))
+        }
+    };
+    let filepath = filepath.to_string_lossy().into_owned();
 
     // 开始下载
-    match download_file_with_progress(url, &filepath).await {
+    match download_file_with_progress(&req.url, &filepath).await {
         Ok(metadata) => HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
             "file": filepath,
             "metadata": metadata
         }))),
         Err(e) => HttpResponse::InternalServerError()
-            .json(ApiResponse::<String>::error(format!("下载失败: {}", e))),
+            .json(ApiResponse::<String>::error(e.context("下载失败"))),
     }
 }
 
 async fn download_file_with_progress(
     url: &str,
     filepath: &str,
-) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
-    use reqwest::Client;
-    use tokio::fs::File;
+) -> Result<serde_json::Value, IpaToolError> {
     use tokio::io::AsyncWriteExt;
 
     let client = Client::new();
     let response = client.get(url).send().await?;
 
     if !response.status().is_success() {
-        return Err(format!("HTTP 错误: {}", response.status()).into());
+        return Err(IpaToolError::Network(format!(
+            "HTTP 错误: {}",
+            response.status()
+        )));
     }
 
-    let total_size = response.content_length().unwrap_or(0);
-    let bytes = response.bytes().await?;
-
-    let mut file = File::create(filepath).await?;
-    file.write_all(&bytes).await?;
-    file.flush().await?;
+    // 边下载边写入磁盘，不把整个文件读入内存
+    let total_size = response.content_length();
+    let file = tokio::fs::File::create(filepath).await?;
+    let result = async {
+        let mut file = tokio::io::BufWriter::new(file);
+        let mut stream = response.bytes_stream();
+        let mut downloaded: u64 = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+        }
+        file.flush().await?;
 
-    let downloaded = bytes.len() as u64;
+        if let Some(total_size) = total_size {
+            if downloaded != total_size {
+                return Err(IpaToolError::Network(format!(
+                    "下载不完整: 期望 {} 字节，实际 {} 字节",
+                    total_size, downloaded
+                )));
+            }
+        }
+        log::info!("下载完成: {} 字节", downloaded);
 
-    if total_size > 0 {
-        let progress = (downloaded as f64 / total_size as f64) * 100.0;
-        log::info!("下载完成: {:.1}% ({}/{})", progress, downloaded, total_size);
+        // 从 IPA 中读取真实的应用信息
+        let path = filepath.to_string();
+        let metadata = tokio::task::spawn_blocking(move || read_ipa_metadata(&path))
+            .await
+            .map_err(|e| IpaToolError::Archive(e.to_string()))?
+            .map_err(|e| e.context("无法读取 IPA 信息"))?;
+        Ok((metadata, downloaded))
     }
+    .await;
 
-    // 返回元数据
-    Ok(serde_json::json!({
-        "bundle_display_name": "Downloaded App",
-        "bundle_short_version_string": "1.0.0",
-        "bundle_id": "com.example.app",
-        "artwork_url": "",
-        "artist_name": "",
-        "file_size": downloaded
-    }))
+    // 下载或解析失败时删除残缺的文件
+    let (metadata, downloaded) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(filepath).await;
+            return Err(e);
+        }
+    };
+
+    let mut value = serde_json::to_value(metadata)?;
+    value["file_size"] = serde_json::json!(downloaded);
+    Ok(value)
 }
 
 // 搜索应用
@@ -358,7 +461,7 @@ async fn search_app(
 
     if term.is_empty() {
         return HttpResponse::BadRequest().json(ApiResponse::<String>::error(
-            "搜索关键词不能为空".to_string(),
+            IpaToolError::InvalidRequest("搜索关键词不能为空".to_string()),
         ));
     }
 
@@ -409,27 +512,285 @@ async fn search_app(
                     }
                     Err(e) => {
                         log::error!("解析搜索结果失败: {}", e);
-                        HttpResponse::InternalServerError()
-                            .json(ApiResponse::<String>::error("解析搜索结果失败".to_string()))
+                        HttpResponse::InternalServerError().json(ApiResponse::<String>::error(
+                            IpaToolError::InvalidResponse("解析搜索结果失败".to_string()),
+                        ))
                     }
                 }
             } else {
                 log::error!("搜索 API 返回错误: {}", response.status());
                 HttpResponse::InternalServerError().json(ApiResponse::<String>::error(
-                    "搜索 API 返回错误".to_string(),
+                    IpaToolError::Network("搜索 API 返回错误".to_string()),
                 ))
             }
         }
         Err(e) => {
             log::error!("搜索请求失败: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiResponse::<String>::error(format!("搜索请求失败: {}", e)))
+            HttpResponse::InternalServerError().json(ApiResponse::<String>::error(
+                IpaToolError::from(e).context("搜索请求失败"),
+            ))
+        }
+    }
+}
+
+// 加密并保存账号密码
+fn store_credentials(data: &AppState, email: &str, password: &str) -> Result<(), IpaToolError> {
+    let credentials = data.encryption.encrypt_credentials(email, password)?;
+    data.db.save_credentials(&credentials)?;
+    Ok(())
+}
+
+// 后台将仍使用旧密钥的凭证迁移到当前密钥
+fn spawn_reencrypt_credentials(data: web::Data<AppState>) {
+    tokio::task::spawn_blocking(
+        move || match data.encryption.reencrypt_credentials(&data.db) {
+            Ok(0) => {}
+            Ok(count) => log::info!("Re-encrypted {} credentials with the current key", count),
+            Err(e) => log::error!("Failed to re-encrypt credentials: {}", e),
+        },
+    );
+}
+
+// 获取已保存的凭证列表（仅返回邮箱，不返回密码）
+async fn list_credentials(data: web::Data<AppState>) -> impl Responder {
+    let result = data.db.get_all_credentials();
+
+    match result {
+        Ok(credentials) => {
+            let list: Vec<Value> = credentials
+                .iter()
+                .map(|c| {
+                    serde_json::json!({
+                        "email": c.email,
+                        "createdAt": c.created_at,
+                        "updatedAt": c.updated_at,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(ApiResponse::success(list))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(ApiResponse::<String>::error(
+            IpaToolError::from(e).context("读取凭证失败"),
+        )),
+    }
+}
+
+// 保存凭证
+async fn save_credentials(
+    req: web::Json<CredentialsRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if req.email.is_empty() || req.password.is_empty() {
+        return HttpResponse::BadRequest().json(ApiResponse::<String>::error(
+            IpaToolError::InvalidRequest("邮箱和密码不能为空".to_string()),
+        ));
+    }
+
+    match store_credentials(&data, &req.email, &req.password) {
+        Ok(()) => HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+            "email": req.email,
+        }))),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(ApiResponse::<String>::error(e.context("保存凭证失败"))),
+    }
+}
+
+// 删除凭证
+async fn delete_credentials(path: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let email = path.into_inner();
+    let result = data.db.delete_credentials(&email);
+
+    match result {
+        Ok(()) => HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+            "email": email,
+        }))),
+        Err(e) => HttpResponse::InternalServerError().json(ApiResponse::<String>::error(
+            IpaToolError::from(e).context("删除凭证失败"),
+        )),
+    }
+}
+
+// 查询密钥轮换状态（不返回密钥本身）
+async fn get_key_rotation(data: web::Data<AppState>) -> impl Responder {
+    let key_manager = data.encryption.key_manager();
+    let info = key_manager.get_key_info();
+
+    HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+        "keyId": info.key_id,
+        "lastRotation": info.last_rotation,
+        "nextRotation": info.next_rotation,
+        "needsRotation": key_manager.needs_rotation(),
+        "previousKeys": key_manager.previous_key_ids(),
+        "scheduler": data.key_rotation.status(),
+    })))
+}
+
+// 手动触发密钥轮换
+async fn rotate_key(data: web::Data<AppState>) -> impl Responder {
+    // 轮换会同步重新加密所有凭证，放到阻塞线程池中执行
+    let key_rotation = data.key_rotation.clone();
+    let result = tokio::task::spawn_blocking(move || key_rotation.rotate_now())
+        .await
+        .unwrap_or_else(|e| Err(IpaToolError::Crypto(e.to_string())));
+    match result {
+        Ok(info) => HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+            "keyId": info.key_id,
+            "lastRotation": info.last_rotation,
+            "nextRotation": info.next_rotation,
+        }))),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(ApiResponse::<String>::error(e.context("密钥轮换失败"))),
+    }
+}
+
+// 已登录账号列表
+async fn list_accounts(data: web::Data<AppState>) -> impl Responder {
+    let mut sessions = data.accounts.list().await;
+    sessions.sort_by(|a, b| a.1.account.account_email.cmp(&b.1.account.account_email));
+
+    let accounts: Vec<Value> = sessions
+        .iter()
+        .map(|(token, session)| {
+            let auth_info = session.account.auth_info.as_ref();
+            serde_json::json!({
+                "token": token,
+                "email": session.account.account_email,
+                "displayName": auth_info.and_then(|info| info.display_name.clone()),
+                "dsid": auth_info.and_then(|info| info.ds_person_id.clone()),
+                "region": session.region,
+                "health": data.accounts.session_health(session),
+                "lastUsed": session.last_used.to_rfc3339(),
+            })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(ApiResponse::success(accounts))
+}
+
+// 注销账号，可选同时删除保存的凭证
+async fn logout_account(
+    path: web::Path<String>,
+    query: web::Query<DeleteAccountQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let token = path.into_inner();
+    let account_store = match data.accounts.revoke(&token).await {
+        Some(account_store) => account_store,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse::<String>::error(
+                IpaToolError::NotFound("账号不存在".to_string()),
+            ))
+        }
+    };
+
+    if query.deleteCredentials {
+        if let Err(e) = data.db.delete_credentials(&account_store.account_email) {
+            return HttpResponse::InternalServerError().json(ApiResponse::<String>::error(
+                IpaToolError::from(e).context("删除凭证失败"),
+            ));
         }
     }
+
+    HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+        "email": account_store.account_email,
+        "credentialsDeleted": query.deleteCredentials,
+    })))
+}
+
+// 使用保存的凭证刷新账号会话
+async fn refresh_login(
+    req: web::Json<RefreshRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data.sessions.refresh(&req.token).await {
+        Ok(account_store) => HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+            "token": req.token,
+            "email": account_store.account_email,
+            "dsid": account_store.auth_info.as_ref().and_then(|info| info.ds_person_id.clone()),
+            "region": account_store.region(),
+        }))),
+        Err(RefreshError::SessionNotFound) => {
+            HttpResponse::Unauthorized().json(ApiResponse::<String>::error(
+                RefreshError::SessionNotFound.into(),
+            ))
+        }
+        Err(e) => HttpResponse::BadRequest().json(ApiResponse::<String>::error(e.into())),
+    }
+}
+
+// 使用保存的凭证自动登录所有账号
+async fn auto_login(data: web::Data<AppState>) -> impl Responder {
+    let credentials = match data.db.get_all_credentials() {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(ApiResponse::<String>::error(
+                IpaToolError::from(e).context("读取凭证失败"),
+            ))
+        }
+    };
+
+    let sessions = data.accounts.list().await;
+    let mut success = Vec::new();
+    let mut need_code = Vec::new();
+    let mut failed = Vec::new();
+
+    for credential in credentials {
+        let logged_in = sessions.iter().find(|(_, session)| {
+            session.account.account_email == credential.email
+                && data.accounts.session_health(session) != SessionHealth::Unauthenticated
+        });
+        if let Some((token, session)) = logged_in {
+            success.push(serde_json::json!({
+                "email": credential.email,
+                "token": token,
+                "dsid": session.account.auth_info.as_ref().and_then(|info| info.ds_person_id.clone()),
+                "alreadyLoggedIn": true,
+            }));
+            continue;
+        }
+
+        match data
+            .sessions
+            .authenticate_with_saved_password(AccountStore::new(&credential.email))
+            .await
+        {
+            Ok((account_store, _)) => {
+                let region = account_store.region();
+                let dsid = account_store
+                    .auth_info
+                    .as_ref()
+                    .and_then(|info| info.ds_person_id.clone());
+                let token = data.accounts.insert(account_store, &region).await;
+                success.push(serde_json::json!({
+                    "email": credential.email,
+                    "token": token,
+                    "dsid": dsid,
+                    "region": region,
+                    "alreadyLoggedIn": false,
+                }));
+            }
+            Err(RefreshError::NeedsMfa) => need_code.push(serde_json::json!({
+                "email": credential.email,
+            })),
+            Err(e) => failed.push(serde_json::json!({
+                "email": credential.email,
+                "error": e.to_string(),
+            })),
+        }
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "ok": true,
+        "results": {
+            "success": success,
+            "needCode": need_code,
+            "failed": failed,
+        }
+    }))
 }
 
 // 登录
-async fn login(req: web::Json<LoginRequest>) -> impl Responder {
+async fn login(req: web::Json<LoginRequest>, data: web::Data<AppState>) -> impl Responder {
     let mut account_store = AccountStore::new(&req.email);
 
     match account_store
@@ -443,19 +804,26 @@ async fn login(req: web::Json<LoginRequest>) -> impl Responder {
                 .unwrap_or("failure");
 
             if state == "success" {
-                // 生成 token
-                let token = uuid::Uuid::new_v4().to_string();
+                complete_login(
+                    &data,
+                    account_store,
+                    &req.password,
+                    req.saveCredentials,
+                    &result,
+                )
+                .await
+            } else if req.mfa.is_none() && is_mfa_required(&result) {
+                // 保存登录状态，客户端只需提交验证码即可完成登录
+                let challenge_id = data.pending_logins.insert(PendingLogin::new(
+                    account_store,
+                    &req.password,
+                    req.saveCredentials,
+                ));
 
-                // 存储账号信息
-                let mut accounts = ACCOUNTS.write().await;
-                accounts.insert(token.clone(), account_store);
-
-                // 返回成功响应
-                HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
-                    "token": token,
-                    "email": req.email,
-                    "displayName": result.get("displayName"),
-                })))
+                HttpResponse::Ok().json(ApiResponse::error_with_data(
+                    IpaToolError::MfaRequired("需要两步验证码".to_string()),
+                    serde_json::json!({ "needsMfa": true, "challengeId": challenge_id }),
+                ))
             } else {
                 // 返回失败响应
                 let error_msg = result
@@ -464,14 +832,347 @@ async fn login(req: web::Json<LoginRequest>) -> impl Responder {
                     .and_then(|v| v.as_str())
                     .unwrap_or("登录失败");
 
-                HttpResponse::BadRequest().json(ApiResponse::<String>::error(error_msg.to_string()))
+                HttpResponse::BadRequest().json(ApiResponse::<String>::error(IpaToolError::Auth(
+                    error_msg.to_string(),
+                )))
             }
         }
         Err(e) => HttpResponse::InternalServerError()
-            .json(ApiResponse::<String>::error(format!("登录失败: {}", e))),
+            .json(ApiResponse::<String>::error(e.context("登录失败"))),
+    }
+}
+
+// 提交两步验证码，完成待验证的登录
+async fn login_mfa(req: web::Json<MfaRequest>, data: web::Data<AppState>) -> impl Responder {
+    let mut pending = match data.pending_logins.take(&req.challengeId) {
+        Some(pending) => pending,
+        None => {
+            return HttpResponse::BadRequest().json(ApiResponse::<String>::error(
+                IpaToolError::Auth("验证已过期，请重新登录".to_string()),
+            ))
+        }
+    };
+
+    match pending
+        .account_store
+        .authenticate(&pending.password, Some(&req.code))
+        .await
+    {
+        Ok(result) => {
+            if result.get("_state").and_then(|v| v.as_str()) == Some("success") {
+                let password = std::mem::take(&mut pending.password);
+                return complete_login(
+                    &data,
+                    pending.account_store,
+                    &password,
+                    pending.save_credentials,
+                    &result,
+                )
+                .await;
+            }
+
+            let error_msg = result
+                .get("customerMessage")
+                .or(result.get("failureType"))
+                .and_then(|v| v.as_str())
+                .unwrap_or("验证码错误")
+                .to_string();
+
+            // 验证码错误时保留挑战，允许重新提交
+            let can_retry =
+                is_mfa_required(&result) && data.pending_logins.retry(&req.challengeId, pending);
+
+            if can_retry {
+                HttpResponse::BadRequest().json(ApiResponse::error_with_data(
+                    IpaToolError::MfaRequired(error_msg),
+                    serde_json::json!({ "needsMfa": true, "challengeId": req.challengeId }),
+                ))
+            } else {
+                HttpResponse::BadRequest()
+                    .json(ApiResponse::<String>::error(IpaToolError::Auth(error_msg)))
+            }
+        }
+        Err(e) => HttpResponse::InternalServerError()
+            .json(ApiResponse::<String>::error(e.context("登录失败"))),
+    }
+}
+
+// 登录成功后注册会话，并按需保存凭证
+async fn complete_login(
+    data: &AppState,
+    account_store: AccountStore,
+    password: &str,
+    save_credentials: bool,
+    result: &HashMap<String, Value>,
+) -> HttpResponse {
+    let email = account_store.account_email.clone();
+
+    // 存储账号信息并生成 token
+    let region = account_store.region();
+    let token = data.accounts.insert(account_store, &region).await;
+
+    if save_credentials {
+        if let Err(e) = store_credentials(data, &email, password) {
+            log::warn!("保存凭证失败: {}", e);
+        }
+    }
+
+    // 返回成功响应
+    HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+        "token": token,
+        "email": email,
+        "displayName": result.get("displayName"),
+        "region": region,
+    })))
+}
+
+fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/health", web::get().to(health))
+        .route("/login", web::post().to(login))
+        .route("/login/mfa", web::post().to(login_mfa))
+        .route("/versions", web::get().to(get_versions))
+        .route("/download-url", web::get().to(get_download_url))
+        .route("/download", web::post().to(download_ipa))
+        .route("/search", web::get().to(search_app))
+        .route("/api/credentials", web::get().to(list_credentials))
+        .route("/api/credentials", web::post().to(save_credentials))
+        .route(
+            "/api/credentials/{email}",
+            web::delete().to(delete_credentials),
+        )
+        .route("/api/login/refresh", web::post().to(refresh_login))
+        .route("/api/auto-login", web::post().to(auto_login))
+        .route("/api/accounts", web::get().to(list_accounts))
+        .route("/api/accounts/{token}", web::delete().to(logout_account))
+        .route("/api/keys/rotation", web::get().to(get_key_rotation))
+        .route("/api/keys/rotation", web::post().to(rotate_key))
+        .route(
+            "/api/start-download-direct",
+            web::post().to(start_download_direct),
+        )
+        .route("/api/job-info", web::get().to(get_job_info))
+        .route("/api/ipa/{id}/info", web::get().to(get_ipa_info))
+        .route("/api/progress-sse", web::get().to(progress_sse));
+}
+
+// 创建后台下载任务，立即返回任务 ID，进度通过 /api/job-info 查询
+async fn start_download_direct(
+    req: HttpRequest,
+    body: web::Json<StartDownloadRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let body = body.into_inner();
+    let request = JobRequest {
+        token: body.token,
+        appid: body.appid,
+        app_ver_id: body.appVerId,
+        auto_purchase: body.autoPurchase,
+        locale: request_locale(&req),
+    };
+
+    match data.jobs.start(request).await {
+        Ok(job_id) => HttpResponse::Ok().json(serde_json::json!({
+            "ok": true,
+            "jobId": job_id,
+        })),
+        Err(e @ IpaToolError::Session(_)) => {
+            HttpResponse::Unauthorized().json(ApiResponse::<String>::error(e))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(ApiResponse::<String>::error(e)),
+    }
+}
+
+async fn get_job_info(query: web::Query<JobQuery>, data: web::Data<AppState>) -> impl Responder {
+    match data.jobs.get(&query.jobId) {
+        Some(job) => HttpResponse::Ok().json(ApiResponse::success(job)),
+        None => HttpResponse::NotFound().json(ApiResponse::<String>::error(
+            IpaToolError::NotFound("任务不存在".to_string()),
+        )),
+    }
+}
+
+// 解析下载记录对应的 IPA：Info.plist、entitlements、加密状态和内嵌组件
+async fn get_ipa_info(path: web::Path<i64>, data: web::Data<AppState>) -> impl Responder {
+    let file = match data.db.get_download_record(path.into_inner()) {
+        Ok(Some(record)) => record.file_path,
+        Ok(None) => None,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(ApiResponse::<String>::error(
+                IpaToolError::from(e).context("读取下载记录失败"),
+            ))
+        }
+    };
+    let Some(file) = file else {
+        return HttpResponse::NotFound().json(ApiResponse::<String>::error(
+            IpaToolError::NotFound("下载记录不存在或没有对应的 IPA 文件".to_string()),
+        ));
+    };
+
+    let result = match data.downloads.contains(&file) {
+        Ok(path) => {
+            let path = path.to_string_lossy().to_string();
+            tokio::task::spawn_blocking(move || inspect_ipa(&path))
+                .await
+                .unwrap_or_else(|e| Err(IpaToolError::Archive(e.to_string())))
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(info) => HttpResponse::Ok().json(ApiResponse::success(info)),
+        Err(e @ IpaToolError::NotFound(_)) => {
+            HttpResponse::NotFound().json(ApiResponse::<String>::error(e))
+        }
+        Err(e @ IpaToolError::InvalidRequest(_)) => {
+            HttpResponse::BadRequest().json(ApiResponse::<String>::error(e))
+        }
+        Err(e) => HttpResponse::InternalServerError()
+            .json(ApiResponse::<String>::error(e.context("解析 IPA 失败"))),
     }
 }
 
+fn sse_event(event: &str, data: &Value) -> web::Bytes {
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+// progress 事件：前端读取 progress.percent / progress.stage，status 为 ready 时开始下载文件
+fn progress_event(job: &JobInfo) -> web::Bytes {
+    sse_event(
+        "progress",
+        &serde_json::json!({
+            "jobId": job.job_id,
+            "status": job.status,
+            "progress": {
+                "percent": job.percent,
+                "stage": job.phase,
+                "message": job.message,
+                "downloaded": job.downloaded,
+                "fileSize": job.file_size,
+                "speed": job.speed,
+                "eta": job.eta,
+            },
+            "error": job.error,
+            "code": job.error_code,
+            "needsPurchase": job.needs_purchase,
+        }),
+    )
+}
+
+fn end_event(job: &JobInfo) -> web::Bytes {
+    sse_event("end", &serde_json::json!({ "status": job.status }))
+}
+
+// 以 SSE 推送任务进度：先补发当前状态，任务结束后发送 end 事件并关闭连接
+async fn progress_sse(query: web::Query<JobQuery>, data: web::Data<AppState>) -> impl Responder {
+    let Some((job, events)) = data.jobs.subscribe(&query.jobId) else {
+        return HttpResponse::NotFound().json(ApiResponse::<String>::error(
+            IpaToolError::NotFound("任务不存在".to_string()),
+        ));
+    };
+
+    let finished = job.status != JobStatus::Running;
+    let mut replay = vec![progress_event(&job)];
+    if finished {
+        replay.push(end_event(&job));
+    }
+
+    let live = futures::stream::unfold((events, finished), |(mut events, finished)| async move {
+        if finished {
+            return None;
+        }
+        loop {
+            match events.recv().await {
+                Ok(JobEvent::Progress { job, line }) => {
+                    let mut chunk = sse_event("log", &serde_json::json!({ "line": line })).to_vec();
+                    chunk.extend_from_slice(&progress_event(&job));
+                    return Some((web::Bytes::from(chunk), (events, false)));
+                }
+                Ok(JobEvent::Finished(job)) => {
+                    let mut chunk = progress_event(&job).to_vec();
+                    chunk.extend_from_slice(&end_event(&job));
+                    return Some((web::Bytes::from(chunk), (events, true)));
+                }
+                // 落后的订阅者跳过中间进度，后续事件仍会带上最新状态
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(
+            futures::stream::iter(replay)
+                .chain(live)
+                .map(Ok::<_, actix_web::Error>),
+        )
+}
+
+// 下载根目录，可通过 IPA_DOWNLOAD_DIR 配置
+fn download_root() -> String {
+    std::env::var("IPA_DOWNLOAD_DIR").unwrap_or_else(|_| "../downloads".to_string())
+}
+
+// 每个下载任务的并发分块数，可通过 IPA_DOWNLOAD_WORKERS 配置
+fn download_workers() -> usize {
+    std::env::var("IPA_DOWNLOAD_WORKERS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_DOWNLOAD_WORKERS)
+}
+
+// 输出文件命名：IPA_FILENAME_TEMPLATE 设置模板，IPA_FILENAME_COLLISION 设置碰撞策略
+// （overwrite / suffix / skip-if-identical）
+fn file_naming() -> FileNaming {
+    let mut naming = FileNaming::default();
+    if let Ok(template) = std::env::var("IPA_FILENAME_TEMPLATE") {
+        naming.template = template;
+    }
+    if let Ok(policy) = std::env::var("IPA_FILENAME_COLLISION") {
+        match CollisionPolicy::parse(&policy) {
+            Some(policy) => naming.collision = policy,
+            None => log::warn!("Unknown filename collision policy: {}", policy),
+        }
+    }
+    naming
+}
+
+// 命令行：server rewrap-keys [--old-key <hex> | --old-key-file <path>]
+// 使用当前环境中的 KEK 重新包装所有数据密钥，旧 KEK 通过参数传入
+fn run_rewrap_keys(db: &Database, args: &[String]) -> std::io::Result<()> {
+    let old_master_key = match (args.first().map(String::as_str), args.get(1)) {
+        (Some("--old-key"), Some(key)) => {
+            Some(MasterKey::from_hex(key).map_err(std::io::Error::other)?)
+        }
+        (Some("--old-key-file"), Some(path)) => {
+            Some(MasterKey::from_file(path).map_err(std::io::Error::other)?)
+        }
+        (None, _) => None,
+        _ => {
+            return Err(std::io::Error::other(
+                "Usage: server rewrap-keys [--old-key <hex> | --old-key-file <path>]",
+            ))
+        }
+    };
+    let new_master_key = MasterKey::from_env().map_err(std::io::Error::other)?;
+    if new_master_key.is_none() {
+        log::warn!("No master key configured, encryption keys will be stored unwrapped");
+    }
+
+    let count = rewrap_encryption_keys(db, old_master_key.as_ref(), new_master_key.as_ref())
+        .map_err(std::io::Error::other)?;
+    log::info!(
+        "Rewrapped {} encryption keys with master key {}",
+        count,
+        new_master_key
+            .as_ref()
+            .map(|k| k.fingerprint())
+            .unwrap_or("<none>")
+    );
+    Ok(())
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
@@ -484,11 +1185,59 @@ async fn main() -> std::io::Result<()> {
         panic!("Database initialization failed: {}", e);
     });
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("rewrap-keys") {
+        return run_rewrap_keys(&db, &args[2..]);
+    }
+
+    let master_key = MasterKey::from_env().unwrap_or_else(|e| {
+        log::error!("Failed to load master key: {}", e);
+        panic!("Master key initialization failed: {}", e);
+    });
+
+    let db = Arc::new(db);
+    let key_manager = Arc::new(KeyManager::with_database(db.clone()).with_master_key(master_key));
+    key_manager.init().unwrap_or_else(|e| {
+        log::error!("Failed to initialize key manager: {}", e);
+        panic!("Key manager initialization failed: {}", e);
+    });
+
+    let encryption = Arc::new(EncryptionService::new(key_manager));
+    let key_rotation = Arc::new(KeyRotationScheduler::new(encryption.clone(), db.clone()));
+
+    let accounts =
+        Arc::new(AccountRegistry::new(Some(db.clone())).with_encryption(encryption.clone()));
+    let sessions = SessionRefresher::new(accounts.clone(), encryption.clone(), db.clone());
+    let downloads = DownloadRoot::new(download_root()).unwrap_or_else(|e| {
+        log::error!("Failed to prepare download directory: {}", e);
+        panic!("Download directory initialization failed: {}", e);
+    });
+    let jobs = JobManager::new(
+        accounts.clone(),
+        sessions.clone(),
+        db.clone(),
+        &downloads.path().to_string_lossy(),
+    )
+    .with_workers(download_workers())
+    .with_naming(file_naming());
+
     let app_state = web::Data::new(AppState {
-        db: Mutex::new(db),
-        accounts: RwLock::new(HashMap::new()),
+        db,
+        accounts,
+        encryption,
+        key_rotation: key_rotation.clone(),
+        sessions,
+        pending_logins: Arc::new(PendingAuthStore::new()),
+        jobs,
+        downloads,
     });
 
+    let restored = app_state.accounts.restore().await;
+    log::info!("Restored {} account sessions", restored);
+    spawn_reencrypt_credentials(app_state.clone());
+    app_state.pending_logins.clone().spawn_sweeper();
+    key_rotation.spawn();
+
     let bind_address = "0.0.0.0:8080";
     log::info!("Starting server at {}", bind_address);
 
@@ -496,14 +1245,390 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .app_data(web::JsonConfig::default().limit(4096))
             .app_data(app_state.clone())
-            .route("/health", web::get().to(health))
-            .route("/login", web::post().to(login))
-            .route("/versions", web::get().to(get_versions))
-            .route("/download-url", web::get().to(get_download_url))
-            .route("/download", web::post().to(download_ipa))
-            .route("/search", web::get().to(search_app))
+            .configure(configure_routes)
     })
     .bind(bind_address)?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test};
+    use ipa_webtool_services::DownloadRecord;
+
+    // 返回固定原始 HTTP 响应的本地服务器
+    async fn serve_raw(response: &'static [u8]) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let _ = socket.write_all(response).await;
+            }
+        });
+        format!("http://{}/Demo.ipa", addr)
+    }
+
+    #[actix_web::test]
+    async fn test_failed_download_removes_file() {
+        let dir = std::env::temp_dir().join(format!("ipa-fetch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filepath = dir.join("Demo.ipa").to_string_lossy().into_owned();
+
+        // 不是有效的 IPA
+        let url = serve_raw(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\nConnection: close\r\n\r\nnot a zip",
+        )
+        .await;
+        assert!(download_file_with_progress(&url, &filepath).await.is_err());
+        assert!(!std::path::Path::new(&filepath).exists());
+
+        // 连接在 Content-Length 之前关闭
+        let url = serve_raw(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\ntruncated",
+        )
+        .await;
+        assert!(download_file_with_progress(&url, &filepath).await.is_err());
+        assert!(!std::path::Path::new(&filepath).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_request_locale() {
+        let req = test::TestRequest::get()
+            .uri("/download-url?lang=en")
+            .insert_header((header::ACCEPT_LANGUAGE, "zh-CN,zh;q=0.9"))
+            .to_http_request();
+        assert_eq!(request_locale(&req), Locale::EnUs);
+
+        let req = test::TestRequest::get()
+            .insert_header((header::ACCEPT_LANGUAGE, "en-US,en;q=0.9"))
+            .to_http_request();
+        assert_eq!(request_locale(&req), Locale::EnUs);
+
+        let req = test::TestRequest::get().to_http_request();
+        assert_eq!(request_locale(&req), Locale::ZhCn);
+    }
+
+    fn test_state() -> web::Data<AppState> {
+        let dir = std::env::temp_dir().join(format!("ipa-server-{}", uuid::Uuid::new_v4()));
+        let db = Arc::new(Database::new(dir.join("test.db").to_str().unwrap()).unwrap());
+        let key_manager = Arc::new(KeyManager::with_database(db.clone()));
+        key_manager.init().unwrap();
+        let encryption = Arc::new(EncryptionService::new(key_manager));
+        let accounts =
+            Arc::new(AccountRegistry::new(Some(db.clone())).with_encryption(encryption.clone()));
+        let sessions = SessionRefresher::new(accounts.clone(), encryption.clone(), db.clone());
+
+        let downloads = DownloadRoot::new(dir.join("downloads")).unwrap();
+
+        web::Data::new(AppState {
+            jobs: JobManager::new(
+                accounts.clone(),
+                sessions.clone(),
+                db.clone(),
+                &downloads.path().to_string_lossy(),
+            ),
+            downloads,
+            sessions,
+            pending_logins: Arc::new(PendingAuthStore::new()),
+            key_rotation: Arc::new(KeyRotationScheduler::new(encryption.clone(), db.clone())),
+            accounts,
+            encryption,
+            db,
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_registered_token_accepted_by_all_routes() {
+        let state = test_state();
+        let token = state
+            .accounts
+            .insert(AccountStore::new("user@example.com"), "US")
+            .await;
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(configure_routes),
+        )
+        .await;
+
+        // 下载地址无法连接，但 token 必须通过校验
+        let req = test::TestRequest::post()
+            .uri("/download")
+            .set_json(serde_json::json!({
+                "token": token,
+                "url": "http://127.0.0.1:9/app.ipa",
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_ne!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // 未认证账号查询下载链接会失败，但不应是 token 无效
+        let req = test::TestRequest::get()
+            .uri(&format!("/download-url?token={}&appid=1", token))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_ne!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_download_path_outside_root_rejected() {
+        let state = test_state();
+        let token = state
+            .accounts
+            .insert(AccountStore::new("user@example.com"), "US")
+            .await;
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(configure_routes),
+        )
+        .await;
+
+        for path in ["../escape", "/tmp", "a/../../escape"] {
+            let req = test::TestRequest::post()
+                .uri("/download")
+                .set_json(serde_json::json!({
+                    "token": token,
+                    "url": "http://127.0.0.1:9/../../app.ipa?x=/etc/passwd",
+                    "downloadPath": path,
+                }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", path);
+            let body: Value = test::read_body_json(resp).await;
+            assert_eq!(body["code"], "invalid_request");
+        }
+        assert!(!state
+            .downloads
+            .path()
+            .parent()
+            .unwrap()
+            .join("escape")
+            .exists());
+    }
+
+    #[actix_web::test]
+    async fn test_list_and_logout_accounts() {
+        let state = test_state();
+        let token = state
+            .accounts
+            .insert(AccountStore::new("user@example.com"), "JP")
+            .await;
+        let credentials = state
+            .encryption
+            .encrypt_credentials("user@example.com", "secret")
+            .unwrap();
+        state.db.save_credentials(&credentials).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(configure_routes),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/api/accounts").to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"][0]["token"], token.as_str());
+        assert_eq!(body["data"][0]["region"], "JP");
+        assert_eq!(body["data"][0]["health"], "unauthenticated");
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/api/accounts/{}?deleteCredentials=true", token))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(state.accounts.get(&token).await.is_none());
+        assert!(state.db.get_account_by_token(&token).unwrap().is_none());
+        assert!(state
+            .db
+            .get_credentials("user@example.com")
+            .unwrap()
+            .is_none());
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/api/accounts/{}", token))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_refresh_without_saved_credentials() {
+        let state = test_state();
+        let token = state
+            .accounts
+            .insert(AccountStore::new("user@example.com"), "US")
+            .await;
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(configure_routes),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/login/refresh")
+            .set_json(serde_json::json!({ "token": token }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::post()
+            .uri("/api/login/refresh")
+            .set_json(serde_json::json!({ "token": "unknown" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_mfa_with_unknown_challenge_rejected() {
+        let state = test_state();
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(configure_routes),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/login/mfa")
+            .set_json(serde_json::json!({ "challengeId": "unknown", "code": "123456" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_or_revoked_token_rejected() {
+        let state = test_state();
+        let token = state
+            .accounts
+            .insert(AccountStore::new("user@example.com"), "US")
+            .await;
+        state.accounts.revoke(&token).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(configure_routes),
+        )
+        .await;
+
+        for token in [token.as_str(), "unknown"] {
+            let req = test::TestRequest::post()
+                .uri("/download")
+                .set_json(serde_json::json!({ "token": token, "url": "http://127.0.0.1:9/a.ipa" }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+            let req = test::TestRequest::get()
+                .uri(&format!("/download-url?token={}&appid=1", token))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let body: Value = test::read_body_json(resp).await;
+            assert_eq!(body["code"], "session_invalid");
+
+            let req = test::TestRequest::post()
+                .uri("/api/start-download-direct")
+                .set_json(serde_json::json!({ "token": token, "appid": "1" }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/api/job-info?jobId=unknown")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_ipa_info_requires_file_inside_root() {
+        let state = test_state();
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(configure_routes),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/ipa/42/info")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let outside = state.downloads.path().parent().unwrap().join("test.db");
+        let record = DownloadRecord {
+            id: None,
+            app_name: "Demo".to_string(),
+            app_id: "1".to_string(),
+            bundle_id: None,
+            version: None,
+            account_email: "user@example.com".to_string(),
+            account_region: None,
+            download_date: None,
+            status: "completed".to_string(),
+            file_size: None,
+            install_url: None,
+            artwork_url: None,
+            artist_name: None,
+            progress: None,
+            error: None,
+            created_at: None,
+            md5: None,
+            file_path: Some(outside.to_string_lossy().to_string()),
+            signed_md5: None,
+            verified: false,
+        };
+        let id = state.db.add_download_record(&record).unwrap();
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/ipa/{}/info", id))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_progress_sse_ends_with_final_status() {
+        let state = test_state();
+        // 未认证的账号会在下载前失败
+        let token = state
+            .accounts
+            .insert(AccountStore::new("user@example.com"), "US")
+            .await;
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(configure_routes),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/start-download-direct")
+            .set_json(serde_json::json!({ "token": token, "appid": "1" }))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        let job_id = body["jobId"].as_str().unwrap();
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/progress-sse?jobId={}", job_id))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("event: progress\n"));
+        assert!(body.ends_with("event: end\ndata: {\"status\":\"failed\"}\n\n"));
+        assert!(body.contains("\"code\":\"session_invalid\""));
+    }
+}
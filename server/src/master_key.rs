@@ -0,0 +1,204 @@
+use crate::crypto::{decrypt_with_key, encrypt_with_key, EncryptedPayload};
+use crate::database::Database;
+use crate::error::IpaToolError;
+use openssl::sha::sha256;
+
+pub const MASTER_KEY_ENV: &str = "IPA_MASTER_KEY";
+pub const MASTER_KEY_FILE_ENV: &str = "IPA_MASTER_KEY_FILE";
+
+const WRAPPED_PREFIX: &str = "kek1";
+
+/// 密钥加密密钥（KEK），用于在写入 `encryption_keys` 表前包装数据密钥
+#[derive(Clone)]
+pub struct MasterKey {
+    key: String,
+    fingerprint: String,
+}
+
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MasterKey")
+            .field("fingerprint", &self.fingerprint)
+            .finish()
+    }
+}
+
+impl MasterKey {
+    pub fn from_hex(key_hex: &str) -> Result<Self, IpaToolError> {
+        let key_hex = key_hex.trim();
+        let bytes = hex::decode(key_hex)?;
+        if bytes.len() != 32 {
+            return Err(IpaToolError::Crypto(
+                "Master key must be 32 bytes (64 hex characters)".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            key: key_hex.to_lowercase(),
+            fingerprint: hex::encode(&sha256(&bytes)[..4]),
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, IpaToolError> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            IpaToolError::Storage(format!("Failed to read master key file {}: {}", path, e))
+        })?;
+        Self::from_hex(&content)
+    }
+
+    /// 从 `IPA_MASTER_KEY` 或 `IPA_MASTER_KEY_FILE` 读取 KEK，均未设置时返回 None
+    pub fn from_env() -> Result<Option<Self>, IpaToolError> {
+        if let Ok(key) = std::env::var(MASTER_KEY_ENV) {
+            return Self::from_hex(&key).map(Some);
+        }
+        if let Ok(path) = std::env::var(MASTER_KEY_FILE_ENV) {
+            return Self::from_file(&path).map(Some);
+        }
+        Ok(None)
+    }
+
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    pub fn wrap(&self, key_hex: &str) -> Result<String, IpaToolError> {
+        let payload = encrypt_with_key(&self.fingerprint, &self.key, key_hex)?;
+        Ok(format!(
+            "{}:{}:{}:{}:{}",
+            WRAPPED_PREFIX, payload.key_id, payload.iv, payload.auth_tag, payload.ciphertext
+        ))
+    }
+
+    pub fn unwrap(&self, stored: &str) -> Result<String, IpaToolError> {
+        let parts: Vec<&str> = stored.split(':').collect();
+        if parts.len() != 5 || parts[0] != WRAPPED_PREFIX {
+            return Err(IpaToolError::Crypto(
+                "Invalid wrapped key format".to_string(),
+            ));
+        }
+        if parts[1] != self.fingerprint {
+            return Err(IpaToolError::Crypto(format!(
+                "Key was wrapped with master key {}, but master key {} is configured",
+                parts[1], self.fingerprint
+            )));
+        }
+
+        decrypt_with_key(
+            &self.key,
+            &EncryptedPayload {
+                key_id: parts[1].to_string(),
+                iv: parts[2].to_string(),
+                auth_tag: parts[3].to_string(),
+                ciphertext: parts[4].to_string(),
+            },
+        )
+    }
+}
+
+pub fn is_wrapped(stored: &str) -> bool {
+    stored.starts_with(&format!("{}:", WRAPPED_PREFIX))
+}
+
+/// 包装数据密钥；未配置 KEK 时原样返回
+pub fn wrap_key(master_key: Option<&MasterKey>, key_hex: &str) -> Result<String, IpaToolError> {
+    match master_key {
+        Some(master_key) => master_key.wrap(key_hex),
+        None => Ok(key_hex.to_string()),
+    }
+}
+
+/// 解包数据密钥；兼容未包装的旧数据
+pub fn unwrap_key(master_key: Option<&MasterKey>, stored: &str) -> Result<String, IpaToolError> {
+    if !is_wrapped(stored) {
+        return Ok(stored.to_string());
+    }
+    match master_key {
+        Some(master_key) => master_key.unwrap(stored),
+        None => Err(IpaToolError::Crypto(
+            "Encryption key is wrapped but no master key is configured".to_string(),
+        )),
+    }
+}
+
+/// 使用新的 KEK 重新包装 `encryption_keys` 表中的所有数据密钥，返回处理的条数
+pub fn rewrap_encryption_keys(
+    db: &Database,
+    old_master_key: Option<&MasterKey>,
+    new_master_key: Option<&MasterKey>,
+) -> Result<usize, IpaToolError> {
+    let keys = db.get_all_encryption_keys()?;
+
+    // 先全部解包，任一失败则不做任何修改
+    let mut rewrapped = Vec::with_capacity(keys.len());
+    for key in &keys {
+        let plain = unwrap_key(old_master_key, &key.key_value)
+            .map_err(|e| e.context(&format!("Failed to unwrap key {}", key.key_id)))?;
+        rewrapped.push((key.key_id.clone(), wrap_key(new_master_key, &plain)?));
+    }
+
+    // 所有更新在一个事务中提交，避免部分密钥使用新 KEK、部分仍使用旧 KEK
+    db.update_encryption_key_values(&rewrapped)?;
+
+    Ok(rewrapped.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap_roundtrip() {
+        let master_key = MasterKey::from_hex(&hex::encode([1u8; 32])).unwrap();
+        let data_key = hex::encode([2u8; 32]);
+
+        let wrapped = master_key.wrap(&data_key).unwrap();
+        assert!(is_wrapped(&wrapped));
+        assert!(!wrapped.contains(&data_key));
+        assert_eq!(master_key.unwrap(&wrapped).unwrap(), data_key);
+
+        let other = MasterKey::from_hex(&hex::encode([3u8; 32])).unwrap();
+        assert!(other.unwrap(&wrapped).is_err());
+    }
+
+    #[test]
+    fn test_unwrap_legacy_plain_key() {
+        let data_key = hex::encode([2u8; 32]);
+        assert_eq!(unwrap_key(None, &data_key).unwrap(), data_key);
+    }
+
+    #[test]
+    fn test_rewrap_updates_are_atomic() {
+        let dir = std::env::temp_dir().join(format!("ipa-master-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Database::new(dir.join("test.db").to_str().unwrap()).unwrap();
+        let data_key = hex::encode([2u8; 32]);
+        db.save_encryption_key(&crate::database::EncryptionKey {
+            id: None,
+            key_id: "k1".to_string(),
+            key_value: data_key.clone(),
+            is_current: true,
+            created_at: None,
+            last_rotation: 0,
+            next_rotation: 0,
+        })
+        .unwrap();
+
+        // 第二个密钥不存在，第一个密钥的更新也必须回滚
+        let values = vec![
+            ("k1".to_string(), "rewrapped".to_string()),
+            ("missing".to_string(), "rewrapped".to_string()),
+        ];
+        assert!(db.update_encryption_key_values(&values).is_err());
+        assert_eq!(db.get_all_encryption_keys().unwrap()[0].key_value, data_key);
+
+        let master_key = MasterKey::from_hex(&hex::encode([1u8; 32])).unwrap();
+        assert_eq!(
+            rewrap_encryption_keys(&db, None, Some(&master_key)).unwrap(),
+            1
+        );
+        let wrapped = db.get_all_encryption_keys().unwrap()[0].key_value.clone();
+        assert_eq!(master_key.unwrap(&wrapped).unwrap(), data_key);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt::Display;
+
+/// 界面语言，默认简体中文
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    ZhCn,
+    EnUs,
+}
+
+impl Locale {
+    /// 解析语言标签，例如 `zh`、`zh-CN`、`en_US`
+    pub fn parse(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        match primary.as_str() {
+            "zh" => Some(Locale::ZhCn),
+            "en" => Some(Locale::EnUs),
+            _ => None,
+        }
+    }
+
+    /// 按 `Accept-Language` 的权重选择支持的语言
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        header
+            .split(',')
+            .filter_map(|part| {
+                let mut fields = part.split(';');
+                let locale = Locale::parse(fields.next()?)?;
+                let quality = fields
+                    .find_map(|f| f.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some((locale, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .fold(None, |best: Option<(Locale, f32)>, candidate| match best {
+                Some(best) if best.1 >= candidate.1 => Some(best),
+                _ => Some(candidate),
+            })
+            .map(|(locale, _)| locale)
+    }
+
+    pub fn tag(self) -> &'static str {
+        match self {
+            Locale::ZhCn => "zh-CN",
+            Locale::EnUs => "en-US",
+        }
+    }
+}
+
+/// 消息目录：错误提示和下载进度文案
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    // 授权和商店错误
+    LicenseNotFound,
+    AppNotFound,
+    NotPurchased,
+    Unauthorized,
+    InvalidRequest,
+    StorefrontMismatch,
+    StorefrontError,
+    SessionExpired,
+    DownloadFailed,
+    ChecksumMismatch,
+    // 下载进度（占位符使用 {name}）
+    QueryDownloadInfo,
+    SessionRefreshing,
+    Purchasing,
+    PurchaseSucceeded,
+    DownloadStarted,
+    DownloadResumed,
+    DownloadProgress,
+    Merging,
+    Verified,
+    Signing,
+    Done,
+    AlreadyDownloaded,
+}
+
+impl Message {
+    pub fn text(self, locale: Locale) -> &'static str {
+        let (zh, en) = match self {
+            Message::LicenseNotFound => (
+                "您尚未购买此应用，正在尝试免费获取...",
+                "You have not purchased this app, trying to get it for free...",
+            ),
+            Message::AppNotFound => (
+                "未找到此应用，请检查 App ID 是否正确",
+                "App not found, please check the App ID",
+            ),
+            Message::NotPurchased => ("您尚未购买此应用", "You have not purchased this app"),
+            Message::Unauthorized => ("无权下载此应用", "Not authorized to download this app"),
+            Message::InvalidRequest => ("无效的请求", "Invalid request"),
+            Message::StorefrontMismatch => (
+                "账号区域与应用不匹配",
+                "The account region does not match the app",
+            ),
+            Message::StorefrontError => (
+                "账号区域错误，请切换账号区域",
+                "Wrong account region, please switch the account region",
+            ),
+            Message::SessionExpired => (
+                "会话已失效，请重新登录",
+                "Session expired, please sign in again",
+            ),
+            Message::DownloadFailed => ("下载失败", "Download failed"),
+            Message::ChecksumMismatch => (
+                "文件校验失败：期望 MD5 {expected}，实际 {actual}",
+                "Checksum mismatch: expected MD5 {expected}, got {actual}",
+            ),
+            Message::QueryDownloadInfo => ("[auth] 查询下载信息", "[auth] Querying download info"),
+            Message::SessionRefreshing => (
+                "[session] 检测到会话失效，尝试刷新...",
+                "[session] Session expired, refreshing...",
+            ),
+            Message::Purchasing => ("[purchase] 正在购买应用...", "[purchase] Purchasing app..."),
+            Message::PurchaseSucceeded => (
+                "[purchase] 购买成功，重新查询下载信息",
+                "[purchase] Purchased, querying download info again",
+            ),
+            Message::DownloadStarted => (
+                "[download] 开始：{size}MB，分块={chunks}",
+                "[download] Started: {size}MB, chunks={chunks}",
+            ),
+            Message::DownloadResumed => (
+                "[download] 继续下载：已有 {downloaded}MB / {size}MB",
+                "[download] Resuming: {downloaded}MB / {size}MB already downloaded",
+            ),
+            Message::DownloadProgress => (
+                "[download] 进度 {downloaded}MB / {size}MB",
+                "[download] Progress {downloaded}MB / {size}MB",
+            ),
+            Message::Merging => ("[merge] 合并分块...", "[merge] Merging chunks..."),
+            Message::Verified => ("[verify] MD5 校验通过", "[verify] MD5 verified"),
+            Message::Signing => ("[sign] 写入签名...", "[sign] Writing signature..."),
+            Message::Done => ("[done] 产物：{file}", "[done] Output: {file}"),
+            Message::AlreadyDownloaded => (
+                "[done] 已存在相同的文件：{file}",
+                "[done] Identical file already exists: {file}",
+            ),
+        };
+
+        match locale {
+            Locale::ZhCn => zh,
+            Locale::EnUs => en,
+        }
+    }
+
+    /// 渲染消息并替换 `{name}` 占位符
+    pub fn render(self, locale: Locale, args: &[(&str, &dyn Display)]) -> String {
+        args.iter()
+            .fold(self.text(locale).to_string(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), &value.to_string())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_language() {
+        assert_eq!(
+            Locale::from_accept_language("en-US,en;q=0.9,zh-CN;q=0.8"),
+            Some(Locale::EnUs)
+        );
+        assert_eq!(
+            Locale::from_accept_language("fr-FR, zh;q=0.7, en;q=0.5"),
+            Some(Locale::ZhCn)
+        );
+        assert_eq!(Locale::from_accept_language("fr-FR, de;q=0.5"), None);
+    }
+
+    #[test]
+    fn test_render_placeholders() {
+        let text = Message::DownloadStarted.render(
+            Locale::EnUs,
+            &[("size", &format!("{:.2}", 12.5)), ("chunks", &3)],
+        );
+        assert_eq!(text, "[download] Started: 12.50MB, chunks=3");
+    }
+}
@@ -0,0 +1,184 @@
+use crate::apple_auth::AccountStore;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+const MAX_MFA_ATTEMPTS: u32 = 3;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 等待两步验证码的登录状态
+#[derive(Clone)]
+pub struct PendingLogin {
+    pub account_store: AccountStore,
+    pub password: String,
+    pub save_credentials: bool,
+    pub attempts: u32,
+    created_at: Instant,
+}
+
+impl PendingLogin {
+    pub fn new(account_store: AccountStore, password: &str, save_credentials: bool) -> Self {
+        Self {
+            account_store,
+            password: password.to_string(),
+            save_credentials,
+            attempts: 0,
+            created_at: Instant::now(),
+        }
+    }
+}
+
+// 不输出密码和会话内容，避免出现在日志中
+impl fmt::Debug for PendingLogin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingLogin")
+            .field("account_email", &self.account_store.account_email)
+            .field("password", &"<redacted>")
+            .field("save_credentials", &self.save_credentials)
+            .field("attempts", &self.attempts)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 保存待完成的 MFA 登录，challenge id -> PendingLogin，超时自动失效
+pub struct PendingAuthStore {
+    pending: Mutex<HashMap<String, PendingLogin>>,
+    ttl: Duration,
+}
+
+impl PendingAuthStore {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            ttl: DEFAULT_CHALLENGE_TTL,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// 保存待验证的登录并返回 challenge id
+    pub fn insert(&self, login: PendingLogin) -> String {
+        let challenge_id = uuid::Uuid::new_v4().to_string();
+        let mut pending = self.pending.lock().unwrap();
+        self.sweep(&mut pending);
+        pending.insert(challenge_id.clone(), login);
+        challenge_id
+    }
+
+    /// 取出待验证的登录，过期或不存在时返回 None
+    pub fn take(&self, challenge_id: &str) -> Option<PendingLogin> {
+        let mut pending = self.pending.lock().unwrap();
+        self.sweep(&mut pending);
+        pending.remove(challenge_id)
+    }
+
+    /// 验证码错误时放回，超过尝试次数后丢弃；返回是否仍可重试
+    pub fn retry(&self, challenge_id: &str, mut login: PendingLogin) -> bool {
+        login.attempts += 1;
+        if login.attempts >= MAX_MFA_ATTEMPTS || login.created_at.elapsed() >= self.ttl {
+            return false;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        self.sweep(&mut pending);
+        pending.insert(challenge_id.to_string(), login);
+        true
+    }
+
+    /// 移除所有过期的登录（连同其中的密码），返回移除的数量
+    pub fn purge_expired(&self) -> usize {
+        self.sweep(&mut self.pending.lock().unwrap())
+    }
+
+    /// 定期清理过期的登录，没有新请求时密码也不会一直留在内存中
+    pub fn spawn_sweeper(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                self.purge_expired();
+            }
+        })
+    }
+
+    fn sweep(&self, pending: &mut HashMap<String, PendingLogin>) -> usize {
+        let before = pending.len();
+        pending.retain(|_, login| login.created_at.elapsed() < self.ttl);
+        before - pending.len()
+    }
+
+    pub fn len(&self) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        self.sweep(&mut pending);
+        pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for PendingAuthStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_expires() {
+        let store = PendingAuthStore::new().with_ttl(Duration::from_millis(10));
+        let login = PendingLogin::new(AccountStore::new("user@example.com"), "pw", false);
+        let challenge_id = store.insert(login);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(store.take(&challenge_id).is_none());
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_expired_logins_are_swept() {
+        let store = PendingAuthStore::new().with_ttl(Duration::from_millis(10));
+        // 先创建账号，避免两次插入之间的耗时超过 TTL
+        let accounts = [
+            AccountStore::new("a@example.com"),
+            AccountStore::new("b@example.com"),
+        ];
+        for account in accounts {
+            store.insert(PendingLogin::new(account, "pw", false));
+        }
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(store.purge_expired(), 2);
+    }
+
+    #[test]
+    fn test_debug_redacts_password() {
+        let login = PendingLogin::new(AccountStore::new("user@example.com"), "hunter2", false);
+        let output = format!("{:?}", login);
+        assert!(output.contains("user@example.com"));
+        assert!(!output.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_retry_limit() {
+        let store = PendingAuthStore::new();
+        let login = PendingLogin::new(AccountStore::new("user@example.com"), "pw", false);
+        let challenge_id = store.insert(login);
+
+        for _ in 0..MAX_MFA_ATTEMPTS - 1 {
+            let login = store.take(&challenge_id).unwrap();
+            assert!(store.retry(&challenge_id, login));
+        }
+        let login = store.take(&challenge_id).unwrap();
+        assert!(!store.retry(&challenge_id, login));
+        assert!(store.take(&challenge_id).is_none());
+    }
+}
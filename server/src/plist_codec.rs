@@ -0,0 +1,163 @@
+use crate::error::IpaToolError;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// `encode_request` 生成的请求体对应的 Content-Type
+pub const PLIST_CONTENT_TYPE: &str = "application/x-apple-plist";
+
+/// 将请求参数编码为 Apple 接口所需的 XML plist
+pub fn encode_request(data: &HashMap<&str, Value>) -> Result<Vec<u8>, IpaToolError> {
+    let dict: plist::Dictionary = data
+        .iter()
+        .map(|(key, value)| (key.to_string(), json_to_plist(value)))
+        .collect();
+
+    let mut buf = Vec::new();
+    plist::Value::Dictionary(dict).to_writer_xml(&mut buf)?;
+    Ok(buf)
+}
+
+/// 解析 Apple 接口返回的 plist（XML 或二进制），不是 plist 时按 JSON 解析
+pub fn decode_response(body: &[u8]) -> Result<HashMap<String, Value>, IpaToolError> {
+    match decode_value(body)? {
+        Value::Object(map) => Ok(map.into_iter().collect()),
+        other => Err(IpaToolError::InvalidResponse(format!(
+            "Unexpected response body: {}",
+            other
+        ))),
+    }
+}
+
+/// 解析响应并反序列化为指定类型
+pub fn decode_response_as<T: DeserializeOwned>(body: &[u8]) -> Result<T, IpaToolError> {
+    Ok(serde_json::from_value(decode_value(body)?)?)
+}
+
+fn decode_value(body: &[u8]) -> Result<Value, IpaToolError> {
+    if is_plist(body) {
+        let value = plist::Value::from_reader(std::io::Cursor::new(body))?;
+        return Ok(plist_to_json(value));
+    }
+    Ok(serde_json::from_slice(body)?)
+}
+
+fn is_plist(body: &[u8]) -> bool {
+    if body.starts_with(b"bplist00") {
+        return true;
+    }
+    let start = body
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(body.len());
+    body[start..].starts_with(b"<?xml") || body[start..].starts_with(b"<plist")
+}
+
+fn plist_to_json(value: plist::Value) -> Value {
+    match value {
+        plist::Value::Dictionary(dict) => Value::Object(
+            dict.into_iter()
+                .map(|(key, value)| (key, plist_to_json(value)))
+                .collect(),
+        ),
+        plist::Value::Array(items) => Value::Array(items.into_iter().map(plist_to_json).collect()),
+        plist::Value::Boolean(b) => Value::Bool(b),
+        // <data> 字段（例如 sinf）统一转为 base64 字符串
+        plist::Value::Data(data) => {
+            Value::String(base64::engine::general_purpose::STANDARD.encode(data))
+        }
+        plist::Value::Date(date) => Value::String(date.to_xml_format()),
+        plist::Value::Integer(i) => i
+            .as_signed()
+            .map(Value::from)
+            .or_else(|| i.as_unsigned().map(Value::from))
+            .unwrap_or(Value::Null),
+        plist::Value::Real(f) => serde_json::Number::from_f64(f)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        plist::Value::String(s) => Value::String(s),
+        plist::Value::Uid(uid) => Value::from(uid.get()),
+        _ => Value::Null,
+    }
+}
+
+fn json_to_plist(value: &Value) -> plist::Value {
+    match value {
+        Value::Object(map) => plist::Value::Dictionary(
+            map.iter()
+                .map(|(key, value)| (key.clone(), json_to_plist(value)))
+                .collect(),
+        ),
+        Value::Array(items) => plist::Value::Array(items.iter().map(json_to_plist).collect()),
+        Value::Bool(b) => plist::Value::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => plist::Value::Integer(i.into()),
+            None => plist::Value::Real(n.as_f64().unwrap_or(0.0)),
+        },
+        Value::String(s) => plist::Value::String(s.clone()),
+        Value::Null => plist::Value::String(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOWNLOAD_RESPONSE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>songList</key>
+    <array>
+        <dict>
+            <key>URL</key>
+            <string>https://iosapps.itunes.apple.com/app.ipa</string>
+            <key>sinfs</key>
+            <array>
+                <dict>
+                    <key>id</key>
+                    <integer>0</integer>
+                    <key>sinf</key>
+                    <data>AAECAw==</data>
+                </dict>
+            </array>
+        </dict>
+    </array>
+    <key>dsPersonId</key>
+    <string>12345</string>
+</dict>
+</plist>"#;
+
+    #[test]
+    fn test_decode_xml_plist() {
+        let result = decode_response(DOWNLOAD_RESPONSE.as_bytes()).unwrap();
+        assert_eq!(result["dsPersonId"], "12345");
+
+        let song = &result["songList"][0];
+        assert_eq!(song["URL"], "https://iosapps.itunes.apple.com/app.ipa");
+        assert_eq!(song["sinfs"][0]["id"], 0);
+        assert_eq!(song["sinfs"][0]["sinf"], "AAECAw==");
+    }
+
+    #[test]
+    fn test_decode_json_fallback() {
+        let result =
+            decode_response(br#"{"failureType": "2034", "customerMessage": "x"}"#).unwrap();
+        assert_eq!(result["failureType"], "2034");
+    }
+
+    #[test]
+    fn test_encode_request_roundtrip() {
+        let mut data = HashMap::new();
+        data.insert("appleId", Value::String("user@example.com".to_string()));
+        data.insert("rmp", Value::from(0));
+
+        let body = encode_request(&data).unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<key>appleId</key>"));
+
+        let decoded = decode_response(&body).unwrap();
+        assert_eq!(decoded["appleId"], "user@example.com");
+        assert_eq!(decoded["rmp"], 0);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::account_registry::AccountRegistry;
+use crate::apple_auth::{is_mfa_required, AccountStore};
+use crate::crypto::EncryptionService;
+use crate::database::Database;
+use crate::error::IpaToolError;
+use crate::file_naming::FileNaming;
+use crate::ipa_handler::{download_ipa_with_account, DownloadParams, DownloadResult, ProgressSink};
+use crate::messages::Locale;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefreshError {
+    SessionNotFound,
+    NoCredentials,
+    NeedsMfa,
+    Failed(String),
+}
+
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshError::SessionNotFound => write!(f, "无效的 token"),
+            RefreshError::NoCredentials => write!(f, "未保存该账号的密码，请重新登录"),
+            RefreshError::NeedsMfa => write!(f, "需要两步验证码，请重新登录"),
+            RefreshError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RefreshError {}
+
+impl From<RefreshError> for IpaToolError {
+    fn from(e: RefreshError) -> Self {
+        match e {
+            RefreshError::SessionNotFound => IpaToolError::Session(e.to_string()),
+            _ => IpaToolError::Auth(e.to_string()),
+        }
+    }
+}
+
+/// 下载任务的参数（不含账号）
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    pub appid: String,
+    pub app_ver_id: Option<String>,
+    pub download_path: String,
+    pub auto_purchase: bool,
+    pub locale: Locale,
+    pub progress: Arc<dyn ProgressSink>,
+    /// 同时下载的分块数
+    pub workers: usize,
+    pub naming: FileNaming,
+    pub client: reqwest::Client,
+}
+
+/// 使用保存的凭证重新认证账号，并替换注册表中的会话
+#[derive(Clone)]
+pub struct SessionRefresher {
+    accounts: Arc<AccountRegistry>,
+    encryption: Arc<EncryptionService>,
+    db: Arc<Database>,
+}
+
+impl SessionRefresher {
+    pub fn new(
+        accounts: Arc<AccountRegistry>,
+        encryption: Arc<EncryptionService>,
+        db: Arc<Database>,
+    ) -> Self {
+        Self {
+            accounts,
+            encryption,
+            db,
+        }
+    }
+
+    /// 读取并解密账号保存的密码
+    pub fn saved_password(&self, email: &str) -> Result<String, RefreshError> {
+        let credentials = self
+            .db
+            .get_credentials(email)
+            .map_err(|e| RefreshError::Failed(format!("读取凭证失败: {}", e)))?
+            .ok_or(RefreshError::NoCredentials)?;
+
+        self.encryption
+            .decrypt_credentials(&credentials)
+            .map_err(|e| RefreshError::Failed(format!("解密凭证失败: {}", e)))
+    }
+
+    /// 使用保存的密码认证账号（不写入注册表）
+    pub async fn authenticate_with_saved_password(
+        &self,
+        mut account_store: AccountStore,
+    ) -> Result<(AccountStore, HashMap<String, Value>), RefreshError> {
+        let password = self.saved_password(&account_store.account_email)?;
+
+        let result = account_store
+            .authenticate(&password, None)
+            .await
+            .map_err(|e| RefreshError::Failed(format!("登录失败: {}", e)))?;
+
+        if result.get("_state").and_then(|v| v.as_str()) == Some("success") {
+            return Ok((account_store, result));
+        }
+
+        if is_mfa_required(&result) {
+            return Err(RefreshError::NeedsMfa);
+        }
+
+        let error_msg = result
+            .get("customerMessage")
+            .or(result.get("failureType"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("登录失败");
+        Err(RefreshError::Failed(error_msg.to_string()))
+    }
+
+    /// 重新认证 token 对应的账号，成功后 token 保持不变
+    pub async fn refresh(&self, token: &str) -> Result<AccountStore, RefreshError> {
+        // 沿用原有的 GUID 和 Cookie，避免 Apple 视为新设备
+        let current = self
+            .accounts
+            .get(token)
+            .await
+            .ok_or(RefreshError::SessionNotFound)?;
+
+        let (account_store, _) = self.authenticate_with_saved_password(current).await?;
+
+        self.accounts.update(token, account_store.clone()).await;
+        log::info!("Refreshed session for {}", account_store.account_email);
+        Ok(account_store)
+    }
+
+    /// 下载 IPA，遇到会话失效时自动重新认证并重试一次
+    pub async fn download_with_reauth(
+        &self,
+        token: &str,
+        options: &DownloadOptions,
+    ) -> Result<DownloadResult, IpaToolError> {
+        let mut account_store = self
+            .accounts
+            .get(token)
+            .await
+            .ok_or(RefreshError::SessionNotFound)?;
+        let mut reauthenticated = false;
+
+        loop {
+            let result = download_ipa_with_account(DownloadParams {
+                store: &account_store,
+                email: &account_store.account_email,
+                appid: &options.appid,
+                app_ver_id: options.app_ver_id.as_deref(),
+                download_path: &options.download_path,
+                auto_purchase: options.auto_purchase,
+                token: Some(token),
+                locale: options.locale,
+                progress: options.progress.clone(),
+                workers: options.workers,
+                naming: &options.naming,
+                client: options.client.clone(),
+            })
+            .await?;
+
+            if !result.needs_reauth || reauthenticated {
+                return Ok(result);
+            }
+
+            reauthenticated = true;
+            match self.refresh(token).await {
+                Ok(refreshed) => account_store = refreshed,
+                Err(e) => {
+                    log::warn!("Automatic re-authentication failed: {}", e);
+                    return Ok(result);
+                }
+            }
+        }
+    }
+}
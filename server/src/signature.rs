@@ -1,9 +1,11 @@
+use crate::error::IpaToolError;
+use crate::store_models::SongListItem;
 use base64::Engine;
 use plist;
 use plist::Value;
 use serde::{Deserialize, Serialize};
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
 use zip::ZipArchive;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,77 +25,58 @@ pub struct Sinf {
     pub sinf: String,
 }
 
+/// 为下载的 IPA 写入 iTunesMetadata.plist 和 sinf 签名
+///
+/// `append_metadata` / `append_signature` 只生成待写入的条目，`write` 时逐个
+/// 原样复制压缩包中的其他条目（不解压）到临时文件再替换原文件，内存占用与 IPA 大小无关。
 #[derive(Debug, Clone)]
 pub struct SignatureClient {
-    archive: Vec<u8>,
     filename: String,
     metadata: SignatureMetadata,
     signature: Option<Sinf>,
     email: String,
+    // 待写入的条目：(路径, 内容)，同名的原条目会被替换
+    entries: Vec<(String, Vec<u8>)>,
 }
 
 impl SignatureClient {
-    pub fn new(
-        song_list_0: &serde_json::Value,
-        email: &str,
-    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn new(song: &SongListItem, email: &str) -> Result<Self, IpaToolError> {
         let metadata = SignatureMetadata {
-            bundle_display_name: song_list_0["metadata"]["bundleDisplayName"]
-                .as_str()
-                .map(|s| s.to_string()),
-            bundle_short_version_string: song_list_0["metadata"]["bundleShortVersionString"]
-                .as_str()
-                .map(|s| s.to_string()),
-            bundle_id: song_list_0["metadata"]["bundleId"]
-                .as_str()
-                .map(|s| s.to_string()),
-            artwork_url: {
-                let url_60 = song_list_0["metadata"]["artworkUrl60"].as_str();
-                let url_512 = song_list_0["metadata"]["artworkUrl512"].as_str();
-                let url_100 = song_list_0["metadata"]["artworkUrl100"].as_str();
-                url_60.or(url_512).or(url_100).map(|s| s.to_string())
-            },
-            artist_name: song_list_0["metadata"]["artistName"]
-                .as_str()
-                .map(|s| s.to_string()),
+            bundle_display_name: song.metadata.bundle_display_name.clone(),
+            bundle_short_version_string: song.metadata.bundle_short_version_string.clone(),
+            bundle_id: song.metadata.bundle_id.clone(),
+            artwork_url: song.metadata.artwork().map(String::from),
+            artist_name: song.metadata.artist_name.clone(),
             apple_id: Some(email.to_string()),
             user_name: Some(email.to_string()),
         };
 
-        let signature = song_list_0["sinfs"]
-            .as_array()
-            .and_then(|sinfs| sinfs.iter().find(|s| s["id"].as_i64() == Some(0)))
-            .map(|s| Sinf {
-                id: 0,
-                sinf: s["sinf"].as_str().unwrap_or("").to_string(),
-            });
+        let signature = song.sinfs.iter().find(|s| s.id == Some(0)).map(|s| Sinf {
+            id: 0,
+            sinf: s.sinf.clone(),
+        });
 
         if signature.is_none() {
-            return Err("Invalid signature".into());
+            return Err(IpaToolError::Archive("Invalid signature".to_string()));
         }
 
         Ok(SignatureClient {
-            archive: Vec::new(),
             filename: String::new(),
             metadata,
             signature,
             email: email.to_string(),
+            entries: Vec::new(),
         })
     }
 
-    pub fn load_file(
-        &mut self,
-        path: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut file = File::open(path)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-        self.archive = buffer;
+    pub fn load_file(&mut self, path: &str) -> Result<(), IpaToolError> {
+        // 只检查文件是否为有效的压缩包，内容在 write 时流式读取
+        read_zip(path)?;
         self.filename = path.to_string();
         Ok(())
     }
 
-    pub fn append_metadata(&mut self) -> &mut Self {
+    pub fn append_metadata(&mut self) -> Result<&mut Self, IpaToolError> {
         let mut dict = plist::Dictionary::new();
         if let Some(name) = &self.metadata.bundle_display_name {
             dict.insert(
@@ -137,53 +120,23 @@ impl SignatureClient {
         let metadata_plist = plist::Value::Dictionary(dict);
         let mut buf = Vec::new();
         let options = plist::XmlWriteOptions::default();
-        plist::to_writer_xml_with_options(&mut buf, &metadata_plist, &options)
-            .map_err(|e| format!("Failed to serialize plist: {}", e))
-            .unwrap();
-        let metadata_content = String::from_utf8(buf)
-            .map_err(|e| format!("Invalid UTF-8: {}", e))
-            .unwrap();
+        plist::to_writer_xml_with_options(&mut buf, &metadata_plist, &options)?;
 
-        let mut archive = zip::ZipWriter::new(std::io::Cursor::new(&mut self.archive));
-        let options: zip::write::FileOptions<'_, ()> = zip::write::FileOptions::default();
-        archive.start_file("iTunesMetadata.plist", options).unwrap();
-        archive.write_all(metadata_content.as_bytes()).unwrap();
-        let _ = archive.finish();
-
-        self.archive.flush().unwrap();
-        self
+        self.add_entry("iTunesMetadata.plist", buf);
+        Ok(self)
     }
 
-    pub fn append_signature(
-        &mut self,
-    ) -> Result<&mut Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn append_signature(&mut self) -> Result<&mut Self, IpaToolError> {
         let signature = match &self.signature {
             Some(s) => s,
-            None => return Err("Invalid signature".into()),
+            None => return Err(IpaToolError::Archive("Invalid signature".to_string())),
         };
 
-        let reader = std::io::Cursor::new(self.archive.clone());
-        let mut zip = ZipArchive::new(reader)?;
-
-        let app_bundle_name = {
-            let mut name = None;
-            for i in 0..zip.len() {
-                let zip_name = zip.by_index(i)?.name().to_string();
-                if zip_name.starts_with("Payload/") && zip_name.ends_with(".app/") {
-                    name = Some(
-                        zip_name
-                            .strip_prefix("Payload/")
-                            .and_then(|s| s.strip_suffix('/'))
-                            .unwrap_or(&zip_name)
-                            .to_string(),
-                    );
-                    break;
-                }
-            }
-            name.ok_or("Could not find app bundle")?
-        };
+        let mut zip = read_zip(&self.filename)?;
+
+        let app_bundle_name = find_app_bundle(&zip)?;
 
-        let manifest_path = format!("{}/SC_Info/Manifest.plist", app_bundle_name);
+        let manifest_path = format!("Payload/{}/SC_Info/Manifest.plist", app_bundle_name);
         let manifest_content = {
             let mut manifest_file = zip.by_name(&manifest_path)?;
             let mut content = String::new();
@@ -194,7 +147,7 @@ impl SignatureClient {
         let manifest: Value = plist::from_reader_xml(manifest_content.as_bytes())
             .unwrap_or_else(|_| Value::Dictionary(Default::default()));
         if manifest == Value::Dictionary(Default::default()) {
-            return Err("Invalid manifest format".into());
+            return Err(IpaToolError::Archive("Invalid manifest format".to_string()));
         }
 
         let sinf_path = if let Value::Dictionary(dict) = &manifest {
@@ -206,49 +159,276 @@ impl SignatureClient {
         } else {
             None
         }
-        .ok_or("Invalid signature: no SinfPaths found")?;
+        .ok_or_else(|| {
+            IpaToolError::Archive("Invalid signature: no SinfPaths found".to_string())
+        })?;
 
-        let signature_target_path = format!("Payload/{}.app/{}", app_bundle_name, sinf_path);
+        let signature_target_path = format!("Payload/{}/{}", app_bundle_name, sinf_path);
         let sinf_bytes = base64::engine::general_purpose::STANDARD.decode(&signature.sinf)?;
 
-        let mut new_archive = zip::ZipWriter::new(std::io::Cursor::new(&mut self.archive));
+        self.add_entry(&signature_target_path, sinf_bytes);
+        Ok(self)
+    }
+
+    fn add_entry(&mut self, path: &str, content: Vec<u8>) {
+        self.entries.retain(|(name, _)| name != path);
+        self.entries.push((path.to_string(), content));
+    }
+
+    /// 流式重写压缩包：原条目按压缩数据原样复制，再追加新条目
+    pub fn write(&mut self) -> Result<(), IpaToolError> {
+        let tmp_path = format!("{}.tmp", self.filename);
+        let result = self
+            .write_to(&tmp_path)
+            .and_then(|_| Ok(std::fs::rename(&tmp_path, &self.filename)?));
+        if result.is_err() {
+            // 写入失败时清理临时文件，避免残留半成品
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    fn write_to(&self, tmp_path: &str) -> Result<(), IpaToolError> {
+        let mut zip = read_zip(&self.filename)?;
+        let tmp = File::create(tmp_path)?;
+        let mut writer = zip::ZipWriter::new(BufWriter::new(tmp));
 
         for i in 0..zip.len() {
-            let mut file = zip.by_index(i)?;
-            if file.name() == signature_target_path {
+            let file = zip.by_index_raw(i)?;
+            if self.entries.iter().any(|(name, _)| name == file.name()) {
                 continue;
             }
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)?;
-            let options: zip::write::FileOptions<'_, ()> = zip::write::FileOptions::default();
-            new_archive.start_file(file.name(), options)?;
-            new_archive.write_all(&buffer)?;
+            writer.raw_copy_file(file)?;
         }
 
-        let options: zip::write::FileOptions<'_, ()> = zip::write::FileOptions::default();
-        new_archive.start_file(&signature_target_path, options)?;
-        new_archive.write_all(&sinf_bytes)?;
-        let _ = new_archive.finish();
-
-        self.archive.flush().unwrap();
-        Ok(self)
-    }
-
-    pub fn write(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(&self.filename)?;
+        for (name, content) in &self.entries {
+            let options: zip::write::FileOptions<'_, ()> = zip::write::FileOptions::default();
+            writer.start_file(name.as_str(), options)?;
+            writer.write_all(content)?;
+        }
 
-        file.write_all(&self.archive)?;
+        writer.finish()?.flush()?;
         Ok(())
     }
 }
 
-pub fn read_zip(
-    path: &str,
-) -> Result<ZipArchive<std::fs::File>, Box<dyn std::error::Error + Send + Sync>> {
+pub fn read_zip(path: &str) -> Result<ZipArchive<std::fs::File>, IpaToolError> {
     let file = File::open(path)?;
     let zip = ZipArchive::new(file)?;
     Ok(zip)
 }
+
+/// 查找 `Payload/` 下的应用包名（如 `Test.app`），压缩包中不一定有目录条目
+pub fn find_app_bundle<R: Read + std::io::Seek>(
+    zip: &ZipArchive<R>,
+) -> Result<String, IpaToolError> {
+    zip.file_names()
+        .filter_map(|name| name.strip_prefix("Payload/")?.split_once('/'))
+        .map(|(bundle, _)| bundle)
+        .find(|bundle| bundle.ends_with(".app"))
+        .map(str::to_string)
+        .ok_or_else(|| IpaToolError::Archive("Could not find app bundle".to_string()))
+}
+
+/// 读取压缩包中的 plist 文件，支持 XML 和二进制格式
+pub fn read_plist_entry<R: Read + std::io::Seek>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Value, IpaToolError> {
+    let mut buffer = Vec::new();
+    zip.by_name(name)?.read_to_end(&mut buffer)?;
+    Ok(Value::from_reader(std::io::Cursor::new(buffer))?)
+}
+
+/// 从 IPA 的 Info.plist 和 iTunesMetadata.plist 读取的应用信息
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IpaMetadata {
+    pub bundle_display_name: Option<String>,
+    pub bundle_short_version_string: Option<String>,
+    pub bundle_id: Option<String>,
+    pub bundle_version: Option<String>,
+    pub minimum_os_version: Option<String>,
+    pub artwork_url: Option<String>,
+    pub artist_name: Option<String>,
+}
+
+pub fn read_ipa_metadata(path: &str) -> Result<IpaMetadata, IpaToolError> {
+    let mut zip = read_zip(path)?;
+    let bundle = find_app_bundle(&zip)?;
+    let info = read_plist_entry(&mut zip, &format!("Payload/{}/Info.plist", bundle))?;
+    let info = info
+        .as_dictionary()
+        .ok_or_else(|| IpaToolError::Archive("Invalid Info.plist".to_string()))?;
+
+    let mut metadata = IpaMetadata {
+        bundle_display_name: plist_string(info, "CFBundleDisplayName")
+            .or_else(|| plist_string(info, "CFBundleName")),
+        bundle_short_version_string: plist_string(info, "CFBundleShortVersionString"),
+        bundle_id: plist_string(info, "CFBundleIdentifier"),
+        bundle_version: plist_string(info, "CFBundleVersion"),
+        minimum_os_version: plist_string(info, "MinimumOSVersion"),
+        artwork_url: None,
+        artist_name: None,
+    };
+
+    // App Store 下载的 IPA 才有 iTunesMetadata.plist
+    if let Ok(Value::Dictionary(itunes)) = read_plist_entry(&mut zip, "iTunesMetadata.plist") {
+        metadata.artwork_url = plist_string(&itunes, "artworkUrl")
+            .or_else(|| plist_string(&itunes, "softwareIcon57x57URL"));
+        metadata.artist_name = plist_string(&itunes, "artistName");
+        if metadata.bundle_display_name.is_none() {
+            metadata.bundle_display_name = plist_string(&itunes, "itemName");
+        }
+    }
+
+    Ok(metadata)
+}
+
+pub fn plist_string(dict: &plist::Dictionary, key: &str) -> Option<String> {
+    dict.get(key).and_then(Value::as_string).map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_models::{AppMetadata, SinfEntry};
+
+    fn write_ipa(path: &str) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        let stored: zip::write::FileOptions<'_, ()> = zip::write::FileOptions::default();
+        zip.add_directory("Payload/Test.app/", stored).unwrap();
+        zip.start_file("Payload/Test.app/SC_Info/Manifest.plist", stored)
+            .unwrap();
+        let mut manifest = plist::Dictionary::new();
+        manifest.insert(
+            "SinfPaths".to_string(),
+            Value::Array(vec![Value::String("SC_Info/Test.sinf".to_string())]),
+        );
+        plist::to_writer_xml(&mut zip, &Value::Dictionary(manifest)).unwrap();
+        zip.start_file("Payload/Test.app/SC_Info/Test.sinf", stored)
+            .unwrap();
+        zip.write_all(b"old").unwrap();
+        zip.start_file("Payload/Test.app/Test", stored).unwrap();
+        zip.write_all(&[7u8; 4096]).unwrap();
+        zip.start_file("Payload/Test.app/Info.plist", stored)
+            .unwrap();
+        let mut info = plist::Dictionary::new();
+        for (key, value) in [
+            ("CFBundleName", "Test"),
+            ("CFBundleIdentifier", "com.example.test"),
+            ("CFBundleShortVersionString", "1.2"),
+            ("CFBundleVersion", "120"),
+            ("MinimumOSVersion", "15.0"),
+        ] {
+            info.insert(key.to_string(), Value::String(value.to_string()));
+        }
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        plist::to_writer_binary(&mut buffer, &Value::Dictionary(info)).unwrap();
+        zip.write_all(buffer.get_ref()).unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_write_replaces_sinf_and_keeps_entries() {
+        let path = std::env::temp_dir()
+            .join(format!("ipa-sign-{}.ipa", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        write_ipa(&path);
+
+        let song = SongListItem {
+            sinfs: vec![SinfEntry {
+                id: Some(0),
+                sinf: base64::engine::general_purpose::STANDARD.encode(b"new"),
+            }],
+            ..Default::default()
+        };
+        let mut client = SignatureClient::new(&song, "user@example.com").unwrap();
+        client.load_file(&path).unwrap();
+        client.append_metadata().unwrap();
+        client.append_signature().unwrap();
+        client.write().unwrap();
+
+        let mut zip = read_zip(&path).unwrap();
+        assert_eq!(zip.len(), 6);
+        let mut sinf = Vec::new();
+        zip.by_name("Payload/Test.app/SC_Info/Test.sinf")
+            .unwrap()
+            .read_to_end(&mut sinf)
+            .unwrap();
+        assert_eq!(sinf, b"new");
+        let mut binary = Vec::new();
+        zip.by_name("Payload/Test.app/Test")
+            .unwrap()
+            .read_to_end(&mut binary)
+            .unwrap();
+        assert_eq!(binary, vec![7u8; 4096]);
+        assert!(zip.by_name("iTunesMetadata.plist").is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_ipa_metadata() {
+        let path = std::env::temp_dir()
+            .join(format!("ipa-meta-{}.ipa", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        write_ipa(&path);
+
+        let metadata = read_ipa_metadata(&path).unwrap();
+        assert_eq!(metadata.bundle_display_name.as_deref(), Some("Test"));
+        assert_eq!(metadata.bundle_id.as_deref(), Some("com.example.test"));
+        assert_eq!(metadata.bundle_version.as_deref(), Some("120"));
+        assert_eq!(metadata.minimum_os_version.as_deref(), Some("15.0"));
+        assert_eq!(metadata.artist_name, None);
+
+        let song = SongListItem {
+            sinfs: vec![SinfEntry {
+                id: Some(0),
+                sinf: String::new(),
+            }],
+            metadata: AppMetadata {
+                artist_name: Some("Example Inc.".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut client = SignatureClient::new(&song, "user@example.com").unwrap();
+        client.load_file(&path).unwrap();
+        client.append_metadata().unwrap();
+        client.write().unwrap();
+
+        let metadata = read_ipa_metadata(&path).unwrap();
+        assert_eq!(metadata.artist_name.as_deref(), Some("Example Inc."));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_failure_removes_tmp_file() {
+        let path = std::env::temp_dir()
+            .join(format!("ipa-sign-{}.ipa", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        write_ipa(&path);
+
+        let song = SongListItem {
+            sinfs: vec![SinfEntry {
+                id: Some(0),
+                sinf: base64::engine::general_purpose::STANDARD.encode(b"new"),
+            }],
+            ..Default::default()
+        };
+        let mut client = SignatureClient::new(&song, "user@example.com").unwrap();
+        client.load_file(&path).unwrap();
+        // 重复的条目名会让 zip 在写入时报错
+        client.entries.push(("dup".to_string(), Vec::new()));
+        client.entries.push(("dup".to_string(), Vec::new()));
+
+        assert!(client.write().is_err());
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
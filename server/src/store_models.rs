@@ -0,0 +1,380 @@
+use crate::error::IpaToolError;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+
+/// `volumeStoreDownloadProduct` 成功时的响应
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadProductResponse {
+    #[serde(default)]
+    pub song_list: Vec<SongListItem>,
+    pub ds_person_id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SongListItem {
+    #[serde(rename = "URL")]
+    pub url: String,
+    pub md5: Option<String>,
+    #[serde(default)]
+    pub sinfs: Vec<SinfEntry>,
+    #[serde(default)]
+    pub metadata: AppMetadata,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SinfEntry {
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub id: Option<i64>,
+    /// base64 编码的 sinf 数据
+    pub sinf: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppMetadata {
+    pub bundle_display_name: Option<String>,
+    pub bundle_short_version_string: Option<String>,
+    #[serde(alias = "softwareVersionBundleId")]
+    pub bundle_id: Option<String>,
+    pub bundle_version: Option<String>,
+    pub artist_name: Option<String>,
+    pub artwork_url: Option<String>,
+    #[serde(rename = "artworkUrl60")]
+    pub artwork_url_60: Option<String>,
+    #[serde(rename = "artworkUrl100")]
+    pub artwork_url_100: Option<String>,
+    #[serde(rename = "artworkUrl512")]
+    pub artwork_url_512: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl AppMetadata {
+    pub fn artwork(&self) -> Option<&str> {
+        self.artwork_url_60
+            .as_deref()
+            .or(self.artwork_url_512.as_deref())
+            .or(self.artwork_url_100.as_deref())
+            .or(self.artwork_url.as_deref())
+    }
+}
+
+/// `buyProduct` 成功时的响应
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LicenseResponse {
+    pub jingle_doc_type: Option<String>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub status: Option<i64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// passwordToken 失效时返回的 failureType
+const PASSWORD_TOKEN_EXPIRED_FAILURES: [i64; 2] = [2034, 2042];
+// 账号凭证无效
+const INVALID_CREDENTIALS_FAILURES: [i64; 1] = [-5000];
+// 应用不在当前账号的商店区域上架
+const STOREFRONT_MISMATCH_FAILURES: [i64; 1] = [1008];
+// 未找到应用授权
+const LICENSE_REQUIRED_FAILURES: [i64; 1] = [9610];
+// 登录失败时 customerMessage 返回的消息键（完整匹配）
+const BAD_LOGIN_MESSAGE: &str = "MZFinance.BadLogin.Configurator_message";
+
+/// Apple 返回的失败信息
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailureResponse {
+    pub failure_type: Option<String>,
+    pub customer_message: Option<String>,
+    pub message: Option<String>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    pub status: Option<i64>,
+    /// 响应的 HTTP 状态码
+    #[serde(skip)]
+    pub http_status: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// 会话失效，需要重新登录
+    SessionExpired,
+    /// passwordToken 已过期（例如修改了密码），需要重新登录
+    PasswordTokenExpired,
+    /// 尚未获得应用授权，需要购买
+    LicenseRequired,
+    /// 账号所在商店区域与应用不匹配
+    StorefrontMismatch,
+    Other,
+}
+
+impl FailureKind {
+    /// 是否可以通过重新认证恢复
+    pub fn needs_reauth(self) -> bool {
+        matches!(
+            self,
+            FailureKind::SessionExpired | FailureKind::PasswordTokenExpired
+        )
+    }
+}
+
+impl FailureResponse {
+    /// 仅根据失败字段判断（不含具体接口的成功标志）
+    fn has_failure_fields(&self) -> bool {
+        let failure_type = self.failure_type.as_deref().unwrap_or("");
+        !failure_type.is_empty()
+            || self.status.is_some_and(|status| status != 0)
+            || self
+                .http_status
+                .is_some_and(|status| !(200..300).contains(&status))
+    }
+
+    /// 数字形式的 failureType，非数字的值返回 None
+    pub fn failure_code(&self) -> Option<i64> {
+        self.failure_type.as_deref()?.trim().parse().ok()
+    }
+
+    /// 根据 failureType、status 和 HTTP 状态码判断失败类型
+    ///
+    /// 不对 customerMessage 做模糊匹配：文案会随语言和版本变化，误判为
+    /// `LicenseRequired` 还会在开启自动购买时触发真实的购买请求。
+    pub fn kind(&self) -> FailureKind {
+        let has_code = |codes: &[i64]| {
+            self.failure_code()
+                .into_iter()
+                .chain(self.status)
+                .any(|code| codes.contains(&code))
+        };
+        if has_code(&PASSWORD_TOKEN_EXPIRED_FAILURES) {
+            FailureKind::PasswordTokenExpired
+        } else if has_code(&INVALID_CREDENTIALS_FAILURES)
+            || matches!(self.http_status, Some(401 | 403))
+            || self.customer_message.as_deref() == Some(BAD_LOGIN_MESSAGE)
+        {
+            FailureKind::SessionExpired
+        } else if has_code(&STOREFRONT_MISMATCH_FAILURES) {
+            FailureKind::StorefrontMismatch
+        } else if has_code(&LICENSE_REQUIRED_FAILURES) {
+            FailureKind::LicenseRequired
+        } else {
+            FailureKind::Other
+        }
+    }
+
+    /// 面向用户的错误信息
+    pub fn display_message(&self) -> String {
+        self.customer_message
+            .as_deref()
+            .or(self.failure_type.as_deref())
+            .or(self.message.as_deref())
+            .filter(|m| !m.is_empty())
+            .map(String::from)
+            .or_else(|| self.http_status.map(|status| format!("HTTP {}", status)))
+            .unwrap_or_else(|| "下载失败".to_string())
+    }
+}
+
+impl From<&FailureResponse> for IpaToolError {
+    fn from(failure: &FailureResponse) -> Self {
+        let message = failure.display_message();
+        match failure.kind() {
+            FailureKind::SessionExpired | FailureKind::PasswordTokenExpired => {
+                IpaToolError::Session(message)
+            }
+            FailureKind::LicenseRequired | FailureKind::StorefrontMismatch => {
+                IpaToolError::License(message)
+            }
+            FailureKind::Other => IpaToolError::Store(message),
+        }
+    }
+}
+
+/// 各接口自身的成功标志
+pub trait StoreSuccess {
+    fn is_success(&self) -> bool;
+}
+
+impl StoreSuccess for DownloadProductResponse {
+    fn is_success(&self) -> bool {
+        !self.song_list.is_empty()
+    }
+}
+
+impl StoreSuccess for LicenseResponse {
+    fn is_success(&self) -> bool {
+        self.jingle_doc_type.as_deref() == Some("purchaseSuccess") || self.status == Some(0)
+    }
+}
+
+/// Store 接口的结果：成功时为具体响应，失败时为 Apple 的失败信息
+#[derive(Debug, Clone)]
+pub enum StoreResponse<T> {
+    Success(T),
+    Failure(FailureResponse),
+}
+
+impl<T: serde::de::DeserializeOwned + StoreSuccess> StoreResponse<T> {
+    /// 根据 HTTP 状态码以及 failureType、customerMessage、status 字段判断结果
+    pub fn classify(http_status: u16, value: Value) -> Result<Self, serde_json::Error> {
+        let mut failure: FailureResponse = serde_json::from_value(value.clone())?;
+        failure.http_status = Some(http_status);
+        if failure.has_failure_fields() {
+            return Ok(StoreResponse::Failure(failure));
+        }
+
+        match serde_json::from_value::<T>(value) {
+            Ok(response) if response.is_success() => Ok(StoreResponse::Success(response)),
+            _ => Ok(StoreResponse::Failure(failure)),
+        }
+    }
+}
+
+// Apple 的数字字段有时是字符串
+fn lenient_i64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::Number(n)) => n.as_i64(),
+        Some(Value::String(s)) => s.parse().ok(),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plist_codec::decode_response_as;
+
+    fn fixture<T: serde::de::DeserializeOwned + StoreSuccess>(
+        http_status: u16,
+        body: &[u8],
+    ) -> StoreResponse<T> {
+        let value: Value = decode_response_as(body).unwrap();
+        StoreResponse::classify(http_status, value).unwrap()
+    }
+
+    fn failure_kind<T: serde::de::DeserializeOwned + StoreSuccess>(
+        response: StoreResponse<T>,
+    ) -> FailureKind {
+        match response {
+            StoreResponse::Success(_) => panic!("expected failure"),
+            StoreResponse::Failure(failure) => failure.kind(),
+        }
+    }
+
+    #[test]
+    fn test_download_success() {
+        let response: StoreResponse<DownloadProductResponse> = fixture(
+            200,
+            include_bytes!("../fixtures/store/download_success.plist"),
+        );
+        let StoreResponse::Success(product) = response else {
+            panic!("expected success");
+        };
+
+        let song = &product.song_list[0];
+        assert_eq!(song.sinfs[0].id, Some(0));
+        assert_eq!(song.metadata.bundle_id.as_deref(), Some("com.example.app"));
+        assert_eq!(song.metadata.artwork(), Some("https://example.com/512.png"));
+        assert!(song
+            .metadata
+            .extra
+            .contains_key("softwareVersionExternalIdentifier"));
+    }
+
+    #[test]
+    fn test_download_failures() {
+        let kind = failure_kind::<DownloadProductResponse>(fixture(
+            200,
+            include_bytes!("../fixtures/store/download_password_token_expired.plist"),
+        ));
+        assert_eq!(kind, FailureKind::PasswordTokenExpired);
+        assert!(kind.needs_reauth());
+
+        let kind = failure_kind::<DownloadProductResponse>(fixture(
+            200,
+            include_bytes!("../fixtures/store/download_license_required.plist"),
+        ));
+        assert_eq!(kind, FailureKind::LicenseRequired);
+
+        let kind = failure_kind::<DownloadProductResponse>(fixture(
+            200,
+            include_bytes!("../fixtures/store/download_storefront_mismatch.plist"),
+        ));
+        assert_eq!(kind, FailureKind::StorefrontMismatch);
+
+        // 没有 failureType 但 songList 为空，同样视为失败
+        let kind = failure_kind::<DownloadProductResponse>(fixture(
+            200,
+            include_bytes!("../fixtures/store/download_empty_song_list.plist"),
+        ));
+        assert_eq!(kind, FailureKind::Other);
+    }
+
+    #[test]
+    fn test_purchase_results() {
+        let response: StoreResponse<LicenseResponse> = fixture(
+            200,
+            include_bytes!("../fixtures/store/purchase_success.plist"),
+        );
+        assert!(matches!(response, StoreResponse::Success(_)));
+
+        let response: StoreResponse<LicenseResponse> = fixture(
+            200,
+            include_bytes!("../fixtures/store/purchase_failure.plist"),
+        );
+        let StoreResponse::Failure(failure) = response else {
+            panic!("expected failure");
+        };
+        assert_eq!(failure.kind(), FailureKind::Other);
+        assert_eq!(failure.display_message(), "An unknown error has occurred.");
+    }
+
+    #[test]
+    fn test_http_status_failure() {
+        let response: StoreResponse<DownloadProductResponse> = fixture(
+            401,
+            include_bytes!("../fixtures/store/download_success.plist"),
+        );
+        assert_eq!(failure_kind(response), FailureKind::SessionExpired);
+    }
+
+    #[test]
+    fn test_kind_ignores_message_text() {
+        let failure = |failure_type: Option<&str>, message: &str| FailureResponse {
+            failure_type: failure_type.map(String::from),
+            customer_message: Some(message.to_string()),
+            http_status: Some(200),
+            ..Default::default()
+        };
+
+        // 文案提到会话或区域，但 failureType 未知，不做猜测
+        for message in [
+            "Your session expired. Please sign in again.",
+            "This item is not available in your country.",
+            "Invalid token",
+            "The item you requested could not be found.",
+            "License not found",
+        ] {
+            assert_eq!(failure(Some("5002"), message).kind(), FailureKind::Other);
+        }
+
+        assert_eq!(
+            failure(Some("-5000"), "").kind(),
+            FailureKind::SessionExpired
+        );
+        assert_eq!(
+            failure(None, BAD_LOGIN_MESSAGE).kind(),
+            FailureKind::SessionExpired
+        );
+        assert_eq!(
+            failure(Some(" 2042 "), "").kind(),
+            FailureKind::PasswordTokenExpired
+        );
+    }
+}